@@ -2,8 +2,8 @@ use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::io::ErrorKind;
-use std::path::PathBuf;
-use tracing::{debug, instrument, trace};
+use std::path::{Path, PathBuf};
+use tracing::{debug, instrument, trace, warn};
 
 use crate::error::ProjectError;
 
@@ -19,9 +19,12 @@ pub struct Manifest {
     pub project: BTreeMap<String, Project>,
     #[serde(default)]
     pub config: Config,
+    /// Globs of other manifests to merge in, relative to this manifest
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
 #[serde_with::skip_serializing_none]
 pub struct Config {
     pub mock_config: Option<String>,
@@ -48,6 +51,24 @@ impl Manifest {
             Some,
         )
     }
+
+    /// Remove the projects whose [`Project::when`] condition is false in `dir`.
+    ///
+    /// # Errors
+    /// - see [`Project::enabled`]
+    pub fn retain_enabled(&mut self, dir: &Path) -> Result<(), ProjectError> {
+        let mut disabled = vec![];
+        for (name, project) in &self.project {
+            if !project.enabled(dir)? {
+                disabled.push(name.clone());
+            }
+        }
+        for name in disabled {
+            debug!("Skipping disabled project {name}");
+            self.project.remove(&name);
+        }
+        Ok(())
+    }
 }
 
 #[derive(Deserialize, PartialEq, Eq, Serialize, Debug, Clone, Default)]
@@ -60,6 +81,11 @@ pub struct Project {
     pub pre_script: Option<PathBuf>,
     pub post_script: Option<PathBuf>,
     pub env: Option<BTreeMap<String, String>>,
+    /// Environment variables holding credentials, e.g. tokens
+    ///
+    /// They are exported like [`Project::env`] but never shown in `Debug` output.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub secrets: BTreeMap<String, Secret>,
     pub alias: Option<Vec<String>>,
     pub scripts: Option<Vec<PathBuf>>,
     #[serde(default)]
@@ -67,6 +93,122 @@ pub struct Project {
     pub labels: BTreeMap<String, String>,
     pub update: Option<PathBuf>,
     pub arches: Option<Vec<String>>,
+    /// Environment variables that must be set to a non-empty value before building
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub required_env: Vec<String>,
+    /// HCL expression that decides if the project is built with `--all` and in CI,
+    /// e.g. `branch == "main"`
+    pub when: Option<String>,
+    /// Values to build the project with, e.g. `{ python = ["3.11", "3.12"] }`
+    ///
+    /// Every combination is a separate build entry, see [`Project::variants`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub matrix: BTreeMap<String, Vec<String>>,
+    /// Applied in order to the built artifact files before they are reported and added to the
+    /// RPM repository
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transforms: Vec<Transform>,
+}
+
+/// An operation on the artifact files of a project, see [`Project::transforms`]
+///
+/// `pattern` is a regex matched against the file name, e.g.
+/// `{ type = "rename", pattern = "^(.+)\\.fc40\\.(.+)\\.rpm$", to = "$1.$2.rpm" }`.
+#[derive(Deserialize, PartialEq, Eq, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Transform {
+    /// Rename matching files, `to` may refer to capture groups like `$1`. Existing files are not
+    /// replaced.
+    Rename { pattern: String, to: String },
+    /// Compress matching files with gzip, adding a `.gz` extension
+    Gzip { pattern: String },
+}
+
+impl Project {
+    /// Evaluate [`Project::when`] in `dir`. Projects without a condition are always enabled.
+    ///
+    /// Besides the usual variables and functions, the expression can use `branch`, the git
+    /// branch checked out in `dir`, or an empty string outside of a git repository.
+    ///
+    /// # Errors
+    /// - the expression is invalid or does not evaluate to a boolean
+    pub fn enabled(&self, dir: &Path) -> Result<bool, ProjectError> {
+        #[derive(Deserialize)]
+        struct When {
+            when: bool,
+        }
+
+        let Some(when) = &self.when else { return Ok(true) };
+        let mut ctx = crate::context::hcl_context();
+        ctx.declare_var("branch", crate::context::git_branch(dir).unwrap_or_default());
+        let res: When = hcl::eval::from_str(&format!("when = {when}\n"), &ctx)?;
+        Ok(res.when)
+    }
+
+    /// Every combination of the values in [`Project::matrix`].
+    ///
+    /// A project without a matrix has a single, empty variant.
+    #[must_use]
+    pub fn variants(&self) -> Vec<BTreeMap<String, String>> {
+        self.matrix.iter().fold(vec![BTreeMap::new()], |variants, (key, values)| {
+            variants
+                .iter()
+                .flat_map(|variant| {
+                    values.iter().map(|value| {
+                        let mut variant = variant.clone();
+                        variant.insert(key.clone(), value.clone());
+                        variant
+                    })
+                })
+                .collect()
+        })
+    }
+
+    /// Check that all [`Project::required_env`] variables are set and not empty.
+    ///
+    /// # Errors
+    /// - [`ProjectError::Multiple`] : one error per missing variable
+    pub fn check_env(&self) -> Result<(), ProjectError> {
+        let missing: Vec<_> = self
+            .required_env
+            .iter()
+            .filter(|var| std::env::var_os(var).is_none_or(|v| v.is_empty()))
+            .map(|var| {
+                ProjectError::Other(format!("Missing required environment variable `{var}`"))
+            })
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(ProjectError::Multiple(missing))
+        }
+    }
+}
+
+/// A value that may contain credentials, e.g. a token in [`Project::secrets`].
+///
+/// The value is redacted in `Debug` output so that it never ends up in logs.
+#[derive(Deserialize, PartialEq, Eq, Serialize, Clone, Default)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    #[must_use]
+    pub const fn new(value: String) -> Self {
+        Self(value)
+    }
+
+    /// The actual value. Do not log it.
+    #[must_use]
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[REDACTED]")
+    }
 }
 
 /// Deserialize the value of the BTreeMap into a String even if they are some other types.
@@ -255,11 +397,6 @@ pub fn load_from_file(path: &PathBuf) -> Result<Manifest, ProjectError> {
         _ => ProjectError::InvalidManifest(e.to_string()),
     })?;
 
-    debug!("Loading config from {path:?}");
-    let mut config = load_from_string(&file)?;
-
-    // recursively merge configs
-
     // get parent path of config file
     let parent = if path.parent().unwrap().as_os_str().is_empty() {
         PathBuf::from(".")
@@ -267,10 +404,17 @@ pub fn load_from_file(path: &PathBuf) -> Result<Manifest, ProjectError> {
         path.parent().unwrap().to_path_buf()
     };
 
-    let walk = ignore::Walk::new(parent);
+    debug!("Loading config from {path:?}");
+    let mut config = load_from_string_in(&file, &parent)?;
+
+    // recursively merge configs
 
     let path = path.canonicalize().expect("Invalid path");
 
+    let mut included = load_includes(&mut config, &parent, &mut vec![path.clone()])?;
+
+    let walk = ignore::Walk::new(parent);
+
     for entry in walk {
         trace!("Found {entry:?}");
         let entry = entry.unwrap();
@@ -281,14 +425,25 @@ pub fn load_from_file(path: &PathBuf) -> Result<Manifest, ProjectError> {
         }
 
         if entry.file_type().unwrap().is_file() && entry.path().file_name().unwrap() == "anda.hcl" {
+            let canonical = entry
+                .path()
+                .canonicalize()
+                .map_err(|e| ProjectError::InvalidManifest(e.to_string()))?;
+            if included.contains(&canonical) {
+                debug!("Skipping {entry:?}, already included");
+                continue;
+            }
             debug!("Loading: {entry:?}");
             let readfile = fs::read_to_string(entry.path())
                 .map_err(|e| ProjectError::InvalidManifest(e.to_string()))?;
 
             let en = entry.path().parent().unwrap();
 
+            let mut nested_config = load_from_string_in(&readfile, en)?;
+            included.extend(load_includes(&mut nested_config, en, &mut vec![canonical])?);
+
             let nested_config = prefix_config(
-                load_from_string(&readfile)?,
+                nested_config,
                 &en.strip_prefix("./").unwrap_or(en).display().to_string(),
             );
             // merge the btreemap
@@ -302,6 +457,98 @@ pub fn load_from_file(path: &PathBuf) -> Result<Manifest, ProjectError> {
     check_config(config)
 }
 
+/// Merge the projects of the manifests matched by `config.include` into `config`.
+///
+/// The globs are relative to `dir`, the directory of the including manifest, and relative paths
+/// inside an included manifest are made relative to its own directory, see [`prefix_paths`].
+/// The `config` block of included manifests is ignored. `stack` holds the canonical paths of
+/// the manifests currently being loaded and is used to detect cycles.
+///
+/// Returns the canonical paths of all included manifests, including nested ones.
+///
+/// # Errors
+/// - invalid glob pattern
+/// - cannot read or parse an included manifest
+/// - a manifest includes itself
+pub fn load_includes(
+    config: &mut Manifest,
+    dir: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> Result<Vec<PathBuf>, ProjectError> {
+    let invalid = |e: &dyn std::fmt::Display| ProjectError::InvalidManifest(e.to_string());
+    let mut paths = vec![];
+    for pattern in std::mem::take(&mut config.include) {
+        let pattern = dir.join(pattern).display().to_string();
+        for path in glob::glob(&pattern).map_err(|e| invalid(&e))? {
+            let path = path.map_err(|e| invalid(&e))?.canonicalize().map_err(|e| invalid(&e))?;
+            if stack.contains(&path) {
+                return Err(ProjectError::IncludeCycle(path));
+            }
+            if paths.contains(&path) {
+                continue;
+            }
+            debug!("Including {path:?}");
+            let file = fs::read_to_string(&path).map_err(|e| invalid(&e))?;
+            let parent = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            let mut included = load_from_string_in(&file, &parent)?;
+            if included.config != Config::default() {
+                warn!("Ignoring the `config` block of included manifest {path:?}");
+            }
+            stack.push(path.clone());
+            let nested = load_includes(&mut included, &parent, stack)?;
+            stack.pop();
+            paths.push(path);
+            paths.extend(nested);
+            for project in included.project.values_mut() {
+                prefix_paths(project, &parent);
+            }
+            config.project.extend(included.project);
+        }
+    }
+    Ok(paths)
+}
+
+/// Join the relative file paths of `project` to `dir`.
+///
+/// This covers the spec, sources and scripts of RPM builds, the Dockerfiles and contexts of
+/// OCI images (unless the context is a URL), the Flatpak manifest and scripts, and the scripts
+/// of the project itself.
+pub fn prefix_paths(project: &mut Project, dir: &Path) {
+    let join = |p: &mut PathBuf| {
+        if p.is_relative() {
+            *p = dir.join(&*p);
+        }
+    };
+    if let Some(rpm) = &mut project.rpm {
+        join(&mut rpm.spec);
+        rpm.sources
+            .iter_mut()
+            .chain(&mut rpm.pre_script)
+            .chain(&mut rpm.post_script)
+            .for_each(join);
+    }
+    for image in
+        project.docker.iter_mut().chain(&mut project.podman).flat_map(|d| d.image.values_mut())
+    {
+        for p in image
+            .dockerfile
+            .iter_mut()
+            .chain(Some(&mut image.context).filter(|c| !c.contains("://")))
+        {
+            if Path::new(p.as_str()).is_relative() {
+                *p = dir.join(&*p).display().to_string();
+            }
+        }
+    }
+    if let Some(flatpak) = &mut project.flatpak {
+        join(&mut flatpak.manifest);
+        flatpak.pre_script.iter_mut().chain(&mut flatpak.post_script).for_each(join);
+    }
+    (project.pre_script.iter_mut().chain(&mut project.post_script).chain(&mut project.update))
+        .chain(project.scripts.iter_mut().flatten())
+        .for_each(join);
+}
+
 #[must_use]
 pub fn prefix_config(mut config: Manifest, prefix: &str) -> Manifest {
     let mut new_config = config.clone();
@@ -383,8 +630,16 @@ pub fn generate_alias(config: &mut Manifest) {
 
 #[instrument]
 pub fn load_from_string(config: &str) -> Result<Manifest, ProjectError> {
+    load_from_string_in(config, Path::new("."))
+}
+
+/// Same as [`load_from_string`], but relative paths in `file()` are resolved against `dir`.
+#[instrument]
+pub fn load_from_string_in(config: &str, dir: &Path) -> Result<Manifest, ProjectError> {
     trace!(config, "Dump config");
-    let mut config: Manifest = hcl::eval::from_str(config, &crate::context::hcl_context())?;
+    let mut config: Manifest = crate::context::with_config_dir(dir, || {
+        hcl::eval::from_str(config, &crate::context::hcl_context())
+    })?;
 
     generate_alias(&mut config);
 
@@ -435,6 +690,205 @@ mod test_parser {
         assert_eq!(config.project["anda"].labels.get("nightly"), Some(&"1".to_owned()));
     }
 
+    #[test]
+    fn test_secret() {
+        let config = load_from_string(
+            r#"
+            project "a" {
+                secrets = {
+                    GITHUB_TOKEN = "ghp_hunter2"
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.project["a"].secrets["GITHUB_TOKEN"].expose(), "ghp_hunter2");
+        let debug = format!("{config:#?}");
+        assert!(debug.contains("[REDACTED]"), "{debug}");
+        assert!(!debug.contains("hunter2"), "{debug}");
+        // the value must survive serialization, e.g. for `anda convert`
+        assert!(to_string(&config).unwrap().contains("ghp_hunter2"));
+    }
+
+    #[test]
+    fn test_when() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        let sig = git2::Signature::now("anda", "anda@example.com").unwrap();
+        let tree = repo.find_tree(repo.treebuilder(None).unwrap().write().unwrap()).unwrap();
+        let oid = repo.commit(None, &sig, &sig, "init", &tree, &[]).unwrap();
+        repo.branch("feature", &repo.find_commit(oid).unwrap(), false).unwrap();
+        repo.set_head("refs/heads/feature").unwrap();
+
+        let mut config = load_from_string(
+            r#"
+            project "always" {}
+            project "main_only" {
+                when = "branch == \"main\""
+            }
+            project "feature_only" {
+                when = "branch != \"main\" && branch != \"\""
+            }
+            "#,
+        )
+        .unwrap();
+        config.retain_enabled(dir.path()).unwrap();
+        assert_eq!(config.project.keys().collect::<Vec<_>>(), ["always", "feature_only"]);
+
+        let project = Project { when: Some("\"yes\"".to_owned()), ..Default::default() };
+        project.enabled(dir.path()).unwrap_err();
+    }
+
+    #[test]
+    fn test_matrix() {
+        let config = load_from_string(
+            r#"
+            project "anda" {
+                matrix = { python = ["3.11", "3.12"], arch = ["x86_64"] }
+            }
+            "#,
+        )
+        .unwrap();
+        let variants = config.project["anda"].variants();
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants[0]["python"], "3.11");
+        assert_eq!(variants[1]["python"], "3.12");
+        assert!(variants.iter().all(|v| v["arch"] == "x86_64"));
+
+        assert_eq!(Project::default().variants(), [BTreeMap::new()]);
+    }
+
+    #[test]
+    fn test_transforms() {
+        let config = load_from_string(
+            r#"
+            project "anda" {
+                transforms = [
+                    { type = "rename", pattern = "^(.+)\\.fc40\\.(.+)\\.rpm$", to = "$1.$2.rpm" },
+                    { type = "gzip", pattern = "\\.src\\.rpm$" },
+                ]
+            }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.project["anda"].transforms,
+            [
+                Transform::Rename {
+                    pattern: r"^(.+)\.fc40\.(.+)\.rpm$".to_owned(),
+                    to: "$1.$2.rpm".to_owned()
+                },
+                Transform::Gzip { pattern: r"\.src\.rpm$".to_owned() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("anda.hcl");
+        fs::create_dir_all(dir.path().join("projects")).unwrap();
+        fs::write(&path, "include = [\"projects/*.hcl\"]\nproject \"base\" {}\n").unwrap();
+        fs::write(dir.path().join("projects/a.hcl"), "project \"a\" {}\n").unwrap();
+        fs::write(dir.path().join("projects/b.hcl"), "project \"b\" {}\n").unwrap();
+
+        let config = load_from_file(&path).unwrap();
+        assert_eq!(config.project.keys().collect::<Vec<_>>(), ["a", "b", "base"]);
+        assert!(config.include.is_empty());
+    }
+
+    #[test]
+    fn test_include_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("anda.hcl");
+        fs::create_dir_all(dir.path().join("sub")).unwrap();
+        fs::write(&path, "include = [\"sub/anda.hcl\"]\nproject \"base\" {}\n").unwrap();
+        fs::write(
+            dir.path().join("sub/anda.hcl"),
+            r#"
+            config {
+                mock_config = "fedora-40-x86_64"
+            }
+            project "a" {
+                rpm {
+                    spec = "a.spec"
+                }
+                docker {
+                    image "a" {
+                        dockerfile = "Dockerfile"
+                        context = "."
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        let config = load_from_file(&path).unwrap();
+        // `sub/anda.hcl` is found by both the include and the directory walk
+        assert_eq!(config.project.keys().collect::<Vec<_>>(), ["a", "base"]);
+        let sub = dir.path().join("sub").canonicalize().unwrap();
+        let a = &config.project["a"];
+        assert_eq!(a.rpm.as_ref().unwrap().spec, sub.join("a.spec"));
+        let image = &a.docker.as_ref().unwrap().image["a"];
+        assert_eq!(
+            image.dockerfile.as_deref(),
+            Some(&*sub.join("Dockerfile").display().to_string())
+        );
+        assert_eq!(image.context, sub.join(".").display().to_string());
+        assert_eq!(config.config.mock_config, None);
+    }
+
+    #[test]
+    fn test_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("anda.hcl");
+        fs::write(dir.path().join("desc.txt"), "A test project").unwrap();
+        fs::write(&path, "project \"a\" {\n  labels {\n    desc = file(\"desc.txt\")\n  }\n}\n")
+            .unwrap();
+
+        let config = load_from_file(&path).unwrap();
+        assert_eq!(config.project["a"].labels["desc"], "A test project");
+
+        fs::write(&path, "project \"a\" {\n  labels {\n    desc = file(\"nope\")\n  }\n}\n")
+            .unwrap();
+        let err = load_from_file(&path).unwrap_err();
+        assert!(err.to_string().contains("nope"), "{err}");
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("anda.hcl");
+        fs::write(&path, "include = [\"other.hcl\"]\nproject \"base\" {}\n").unwrap();
+        fs::write(dir.path().join("other.hcl"), "include = [\"anda.hcl\"]\nproject \"a\" {}\n")
+            .unwrap();
+
+        let err = load_from_file(&path).unwrap_err();
+        assert!(matches!(err, ProjectError::IncludeCycle(p) if p == path.canonicalize().unwrap()));
+    }
+
+    #[test]
+    fn test_required_env() {
+        std::env::set_var("ANDA_TEST_REQUIRED_SET", "1");
+        std::env::set_var("ANDA_TEST_REQUIRED_EMPTY", "");
+        let config = load_from_string(
+            r#"
+            project "anda" {
+                required_env = ["ANDA_TEST_REQUIRED_SET", "ANDA_TEST_REQUIRED_EMPTY", "ANDA_TEST_REQUIRED_UNSET"]
+            }
+            "#,
+        )
+        .unwrap();
+
+        let err = config.project["anda"].check_env().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Multiple errors:\n - Missing required environment variable `ANDA_TEST_REQUIRED_EMPTY`\n - Missing required environment variable `ANDA_TEST_REQUIRED_UNSET`"
+        );
+        Project::default().check_env().unwrap();
+    }
+
     #[test]
     fn test_map() {
         let m = [("foo".to_owned(), "bar".to_owned())].into();
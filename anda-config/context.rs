@@ -1,5 +1,8 @@
+use std::cell::RefCell;
 use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
+use git2::{DescribeFormatOptions, DescribeOptions, Repository};
 use hcl::eval::{Context, FuncArgs, FuncDef};
 use hcl::Value;
 
@@ -10,6 +13,94 @@ use parking_lot::Mutex;
 // todo: let this be mutable
 static GLOBAL_CONTEXT: OnceCell<Mutex<Context>> = OnceCell::new();
 
+thread_local! {
+    /// Directory of the manifest being loaded, see [`with_config_dir`]
+    static CONFIG_DIR: RefCell<PathBuf> = RefCell::new(PathBuf::from("."));
+}
+
+/// Resolve relative paths passed to the `file()` HCL function and the `git_*` functions against
+/// `dir` while running `f`.
+///
+/// HCL functions cannot capture state, so the directory is kept in a thread local.
+pub fn with_config_dir<T, F: FnOnce() -> T>(dir: &Path, f: F) -> T {
+    let old = CONFIG_DIR.replace(dir.to_path_buf());
+    let res = f();
+    CONFIG_DIR.set(old);
+    res
+}
+
+/// Get the full hash of the commit `HEAD` points to.
+///
+/// # Errors
+/// - `path` is not inside a git repository
+/// - `HEAD` does not point to a commit (e.g. no commits yet)
+pub fn git_commit(path: &Path) -> Result<String, git2::Error> {
+    let repo = Repository::discover(path)?;
+    let commit = repo.head()?.peel_to_commit()?;
+    Ok(commit.id().to_string())
+}
+
+/// Get the name of the currently checked out branch.
+///
+/// # Errors
+/// - `path` is not inside a git repository
+/// - `HEAD` is detached
+pub fn git_branch(path: &Path) -> Result<String, git2::Error> {
+    let repo = Repository::discover(path)?;
+    let head = repo.head()?;
+    if !head.is_branch() {
+        return Err(git2::Error::from_str("HEAD is not a branch"));
+    }
+    head.shorthand().map(str::to_owned).ok_or_else(|| git2::Error::from_str("Invalid branch name"))
+}
+
+/// Get the tag pointing exactly at `HEAD`.
+///
+/// # Errors
+/// - `path` is not inside a git repository
+/// - no tag points at `HEAD`
+pub fn git_tag(path: &Path) -> Result<String, git2::Error> {
+    let repo = Repository::discover(path)?;
+    let describe = repo.describe(DescribeOptions::new().describe_tags().max_candidates_tags(0))?;
+    describe.format(None)
+}
+
+/// Equivalent to `git describe --tags --always`.
+///
+/// # Errors
+/// - `path` is not inside a git repository
+/// - `HEAD` does not point to a commit
+pub fn git_describe(path: &Path) -> Result<String, git2::Error> {
+    let repo = Repository::discover(path)?;
+    let describe =
+        repo.describe(DescribeOptions::new().describe_tags().show_commit_oid_as_fallback(true))?;
+    describe.format(Some(DescribeFormatOptions::new().abbreviated_size(7)))
+}
+
+/// Declare the `git_*` variables and functions in an HCL context.
+///
+/// The variables are evaluated once against `path` and are empty strings outside of a git
+/// repository. The functions are evaluated on every call against the directory of the manifest
+/// being loaded, see [`with_config_dir`], and fail outside of a git repository.
+pub fn declare_git(ctx: &mut Context<'_>, path: &Path) {
+    macro_rules! git_fn {
+        ($name:ident) => {{
+            let f = |_: FuncArgs| {
+                CONFIG_DIR
+                    .with_borrow(|dir| $name(dir))
+                    .map(Value::String)
+                    .map_err(|e| e.to_string())
+            };
+            ctx.declare_func(stringify!($name), FuncDef::builder().build(f));
+            ctx.declare_var(stringify!($name), Value::String($name(path).unwrap_or_default()));
+        }};
+    }
+    git_fn!(git_commit);
+    git_fn!(git_branch);
+    git_fn!(git_tag);
+    git_fn!(git_describe);
+}
+
 /// Generate Context for HCL evaluation
 ///
 /// # Panics
@@ -23,20 +114,89 @@ pub fn hcl_context() -> Context<'static> {
         let value = env.get(key).ok_or("Key not found in environment variables")?;
         Ok(Value::String(value.to_string()))
     };
+    // content of a file, relative to the manifest being loaded
+    let file_func = |args: FuncArgs| {
+        let path = args.first().and_then(|v| v.as_str()).ok_or("Invalid argument")?;
+        let path = CONFIG_DIR.with_borrow(|dir| dir.join(path));
+        std::fs::read_to_string(&path)
+            .map(Value::String)
+            .map_err(|e| format!("Cannot read `{}`: {e}", path.display()))
+    };
     let c = GLOBAL_CONTEXT.get_or_init(|| {
         dotenv::dotenv().ok();
         let mut ctx = Context::new();
         let env_func = FuncDef::builder().param(hcl::eval::ParamType::String).build(env_func);
         ctx.declare_func("env", env_func);
+        let file_func = FuncDef::builder().param(hcl::eval::ParamType::String).build(file_func);
+        ctx.declare_func("file", file_func);
 
-        let env = std::env::vars().collect::<BTreeMap<String, String>>();
-        let mut map = hcl::Map::new();
+        Mutex::new(ctx)
+    });
+    let mut ctx = c.lock().clone();
 
-        map.extend(env.into_iter().map(|(k, v)| (k, Value::String(v))));
+    // the manifest directory may change between loads, e.g. for included manifests
+    CONFIG_DIR.with_borrow(|dir| declare_git(&mut ctx, dir));
 
-        ctx.declare_var("env", Value::Object(map));
+    // the environment may change between loads, e.g. when multiple manifests are loaded
+    let env = std::env::vars().collect::<BTreeMap<String, String>>();
+    let mut map = hcl::Map::new();
 
-        Mutex::new(ctx)
-    });
-    c.lock().clone()
+    map.extend(env.into_iter().map(|(k, v)| (k, Value::String(v))));
+
+    ctx.declare_var("env", Value::Object(map));
+    ctx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hcl::eval::Evaluate;
+    use std::str::FromStr;
+
+    fn init_repo(dir: &Path) -> String {
+        let repo = Repository::init(dir).unwrap();
+        let sig = git2::Signature::now("anda", "anda@example.com").unwrap();
+        let tree = repo.treebuilder(None).unwrap().write().unwrap();
+        let tree = repo.find_tree(tree).unwrap();
+        let oid = repo.commit(Some("HEAD"), &sig, &sig, "init", &tree, &[]).unwrap();
+        let commit = repo.find_commit(oid).unwrap();
+        repo.tag_lightweight("v1.0.0", commit.as_object(), false).unwrap();
+        oid.to_string()
+    }
+
+    #[test]
+    fn test_git_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let oid = init_repo(dir.path());
+
+        assert_eq!(git_commit(dir.path()).unwrap(), oid);
+        assert_eq!(git_tag(dir.path()).unwrap(), "v1.0.0");
+        assert_eq!(git_describe(dir.path()).unwrap(), "v1.0.0");
+
+        let mut ctx = Context::new();
+        declare_git(&mut ctx, dir.path());
+        let template = hcl::template::Template::from_str("${git_commit}-${git_describe}").unwrap();
+        assert_eq!(template.evaluate(&ctx).unwrap(), format!("{oid}-v1.0.0"));
+    }
+
+    #[test]
+    fn test_git_context_config_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let oid = init_repo(dir.path());
+
+        let template = hcl::template::Template::from_str("${git_commit()}-${git_tag}").unwrap();
+        let res = with_config_dir(dir.path(), || template.evaluate(&hcl_context()));
+        assert_eq!(res.unwrap(), format!("{oid}-v1.0.0"));
+    }
+
+    #[test]
+    fn test_git_context_no_repo() {
+        let dir = tempfile::tempdir().unwrap();
+        git_commit(dir.path()).unwrap_err();
+
+        let mut ctx = Context::new();
+        declare_git(&mut ctx, dir.path());
+        let template = hcl::template::Template::from_str("${git_commit}").unwrap();
+        assert_eq!(template.evaluate(&ctx).unwrap(), "");
+    }
 }
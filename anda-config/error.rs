@@ -8,6 +8,8 @@ pub enum ProjectError {
     InvalidManifest(String),
     Multiple(Vec<Self>),
     HclError(hcl::error::Error),
+    /// A manifest includes itself, directly or through other includes
+    IncludeCycle(std::path::PathBuf),
     Other(String),
 }
 
@@ -23,6 +25,7 @@ impl std::fmt::Display for ProjectError {
             Self::NoManifest => write!(f, "No manifest found"),
             Self::InvalidManifest(e) => write!(f, "Invalid manifest: {e}"),
             Self::Other(msg) => write!(f, "{msg}"),
+            Self::IncludeCycle(p) => write!(f, "Manifest includes itself: {}", p.display()),
             Self::HclError(e) => write!(
                 f,
                 "Error parsing HCL: {e}{}",
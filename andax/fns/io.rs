@@ -1,4 +1,4 @@
-use crate::error::AndaxRes;
+use crate::{error::AndaxRes, run::rf};
 use rhai::{
     plugin::{
         export_module, mem, Dynamic, FnNamespace, ImmutableString, Module, NativeCallContext,
@@ -11,13 +11,15 @@ use std::process::Command;
 use tracing::{debug, instrument};
 
 macro_rules! _sh_out {
-    ($ctx:expr, $o:expr) => {
+    ($ctx:expr, $o:expr) => {{
+        // bind first, `$o` runs the command
+        let o = $o;
         Ok((
-            _sh_out!($o)?,
-            String::from_utf8($o.stdout).ehdl($ctx)?,
-            String::from_utf8($o.stderr).ehdl($ctx)?,
+            _sh_out!(o)?,
+            String::from_utf8(o.stdout).ehdl($ctx)?,
+            String::from_utf8(o.stderr).ehdl($ctx)?,
         ))
-    };
+    }};
     ($o:expr) => {{
         $o.status.code().ok_or::<Box<EvalAltResult>>("No exit code".into())
     }};
@@ -44,8 +46,41 @@ macro_rules! _stream_cmd {
 
 type T = Result<(i32, String, String), Box<EvalAltResult>>;
 
+/// turn an array of strings into a command
+fn arr_cmd(arr: rhai::Array) -> Result<Command, Box<EvalAltResult>> {
+    let arr: Vec<String> = arr
+        .into_iter()
+        .map(|a| a.into_string().map_err(|t| format!("expected string, found {t}").into()))
+        .collect::<Result<_, Box<EvalAltResult>>>()?;
+    let [exec, args @ ..] = &arr[..] else { return Err("empty command".into()) };
+    let mut cmd = Command::new(exec);
+    cmd.args(args);
+    Ok(cmd)
+}
+
+/// run a command and turn its output into `#{stdout, stderr, code}`
+///
+/// `code` is `()` if the command was killed by a signal.
+/// If `check` is true, a command that does not exit successfully is an error instead.
+fn output_map(
+    ctx: &NativeCallContext,
+    mut cmd: Command,
+    check: bool,
+) -> Result<rhai::Map, Box<EvalAltResult>> {
+    let out = cmd.output().ehdl(ctx)?;
+    let stderr = String::from_utf8_lossy(&out.stderr).into_owned();
+    if check && !out.status.success() {
+        return Err(format!("{cmd:?} failed ({}): {}", out.status, stderr.trim()).into());
+    }
+    let mut map = rhai::Map::new();
+    map.insert("stdout".into(), String::from_utf8_lossy(&out.stdout).into_owned().into());
+    map.insert("stderr".into(), stderr.into());
+    map.insert("code".into(), out.status.code().map_or(Dynamic::UNIT, |c| i64::from(c).into()));
+    Ok(map)
+}
+
 /// for andax, shell():
-/// ```
+/// ```rhai
 /// sh("echo hai");
 /// sh(["echo", "hai"]);
 /// sh(["rm", "-rf", "/path/with/some space"]);
@@ -74,8 +109,8 @@ pub mod ar {
 
     /// get the return code from the return value of `sh()`
     #[rhai_fn(global)]
-    pub fn sh_rc(o: (i32, String, String)) -> i32 {
-        o.0
+    pub fn sh_rc(o: (i32, String, String)) -> i64 {
+        i64::from(o.0)
     }
     /// get stdout from the return value of `sh()`
     #[rhai_fn(global)]
@@ -186,16 +221,63 @@ pub mod ar {
     /// run an executable
     #[instrument(skip(ctx))]
     #[rhai_fn(return_raw, name = "sh", global)]
-    pub fn sh(ctx: NativeCallContext, cmd: Vec<&str>) -> T {
+    pub fn sh(ctx: NativeCallContext, cmd: rhai::Array) -> T {
         debug!("Running executable");
-        _sh_out!(&ctx, Command::new(cmd[0]).args(&cmd[1..]).output().ehdl(&ctx)?)
+        _sh_out!(&ctx, arr_cmd(cmd)?.output().ehdl(&ctx)?)
     }
     /// run an executable in working directory
     #[instrument(skip(ctx))]
     #[rhai_fn(return_raw, name = "sh", global)]
-    pub fn sh_cwd(ctx: NativeCallContext, cmd: Vec<&str>, cwd: &str) -> T {
+    pub fn sh_cwd(ctx: NativeCallContext, cmd: rhai::Array, cwd: &str) -> T {
         debug!("Running executable");
-        _sh_out!(&ctx, Command::new(cmd[0]).args(&cmd[1..]).current_dir(cwd).output().ehdl(&ctx)?)
+        _sh_out!(&ctx, arr_cmd(cmd)?.current_dir(cwd).output().ehdl(&ctx)?)
+    }
+
+    /// run `program` with `args` and capture its output
+    ///
+    /// Returns `#{stdout, stderr, code}`.
+    /// ## Example
+    /// ```rhai
+    /// let head = cmd("git", ["rev-parse", "HEAD"]).stdout;
+    /// ```
+    #[instrument(skip(ctx))]
+    #[rhai_fn(return_raw, global)]
+    pub fn cmd(
+        ctx: NativeCallContext,
+        program: &str,
+        args: rhai::Array,
+    ) -> Result<rhai::Map, Box<EvalAltResult>> {
+        cmd_check(ctx, program, args, false)
+    }
+    /// same as `cmd(program, args)`, but throws if `check` is true and `program` fails
+    #[instrument(skip(ctx))]
+    #[rhai_fn(return_raw, name = "cmd", global)]
+    pub fn cmd_check(
+        ctx: NativeCallContext,
+        program: &str,
+        args: rhai::Array,
+        check: bool,
+    ) -> Result<rhai::Map, Box<EvalAltResult>> {
+        let mut arr = vec![program.into()];
+        arr.extend(args);
+        output_map(&ctx, arr_cmd(arr)?, check)
+    }
+    /// run a shell one-liner and capture its output as `#{stdout, stderr, code}`
+    ///
+    /// Throws if `check` is true and the script fails.
+    /// ## Example
+    /// ```rhai
+    /// let files = sh("ls | wc -l", true).stdout;
+    /// ```
+    #[instrument(skip(ctx))]
+    #[rhai_fn(return_raw, name = "sh", global)]
+    pub fn shell_check(
+        ctx: NativeCallContext,
+        script: &str,
+        check: bool,
+    ) -> Result<rhai::Map, Box<EvalAltResult>> {
+        debug!("Running in shell");
+        output_map(&ctx, _cmd!(script), check)
     }
     /// list files and folders in directory
     /// ## Example
@@ -216,6 +298,20 @@ pub mod ar {
     pub fn ls_cwd(ctx: NativeCallContext) -> Result<rhai::Array, Box<EvalAltResult>> {
         ls(ctx, ".")
     }
+    /// download a file, fails immediately if `ANDA_OFFLINE` is set
+    ///
+    /// ## Example
+    /// ```rhai
+    /// download("https://example.com/foo.tar.gz", "foo.tar.gz");
+    /// ```
+    #[rhai_fn(return_raw, global)]
+    pub fn download(
+        ctx: NativeCallContext,
+        url: &str,
+        file: &str,
+    ) -> Result<(), Box<EvalAltResult>> {
+        rf(&ctx, crate::fns::tsunagu::download(url, file.as_ref(), crate::fns::tsunagu::offline()))
+    }
     /// write data to file
     ///
     /// ## Example
@@ -256,6 +352,7 @@ mod test {
             let c = sh(["rm", "-rf", "test"]);
             let d = sh("ls -al", "/");
             let pwd = sh("pwd").sh_stdout();
+            pwd.trim();
             let e = sh(["grep", "hai", "test"], pwd);
             if a.sh_stderr() != "" {
                 throw "error!?";
@@ -270,17 +367,33 @@ mod test {
                 throw "why is out empty?";
             }
             let f = sh("pwd", #{"stdout": "piped", "cwd": "/"});
-            if f.outcome != "succes" {
+            if f.outcome != "success" {
                 throw "not success??";
             }
             if f.ctx.rc != 0 {
                 throw `pwd returned ${f.ctx.rc}`;
             }
-            if f.ctx.stdout != "/" {
+            if f.ctx.stdout != "/\n" {
                 throw "cwd doesn't work?";
             }
         "#,
         )?;
         Ok(())
     }
+
+    #[test]
+    fn cmd_output() {
+        let (en, _) = crate::run::gen_en();
+        let out: rhai::Map = en.eval(r#"cmd("echo", ["hai", "there"])"#).unwrap();
+        assert_eq!(out["stdout"].clone().into_string().unwrap(), "hai there\n");
+        assert_eq!(out["code"].as_int().unwrap(), 0);
+
+        let out: rhai::Map = en.eval(r#"sh("echo oops >&2; exit 3", false)"#).unwrap();
+        assert_eq!(out["stderr"].clone().into_string().unwrap(), "oops\n");
+        assert_eq!(out["code"].as_int().unwrap(), 3);
+
+        let err = en.run(r#"sh("echo oops >&2; exit 3", true)"#).unwrap_err();
+        assert!(err.to_string().contains("oops"), "{err}");
+        assert!(en.run(r#"cmd("false", [], true)"#).is_err());
+    }
 }
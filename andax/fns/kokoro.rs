@@ -29,6 +29,16 @@ pub mod ar {
     pub fn json_arr(ctx: NativeCallContext, a: String) -> Res<rhai::Array> {
         serde_json::from_str(&a).ehdl(&ctx)
     }
+    /// parse a JSON string of any type, e.g. a downloaded file
+    #[rhai_fn(return_raw, global)]
+    pub fn parse_json(ctx: NativeCallContext, a: &str) -> Res<Dynamic> {
+        serde_json::from_str(a).ehdl(&ctx)
+    }
+    /// parse a TOML document into a map
+    #[rhai_fn(return_raw, global)]
+    pub fn parse_toml(ctx: NativeCallContext, a: &str) -> Res<Dynamic> {
+        rhai::serde::to_dynamic(toml::from_str::<toml::Value>(a).ehdl(&ctx)?)
+    }
     #[rhai_fn(return_raw, global)]
     pub fn find(ctx: NativeCallContext, r: &str, text: &str, group: i64) -> Res<String> {
         let captures = Regex::new(r).ehdl(&ctx)?.captures(text);
@@ -59,3 +69,22 @@ pub mod ar {
         chrono::offset::Utc::now().format("%Y%m%d").to_string()
     }
 }
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn parse() {
+        let (en, _) = crate::run::gen_en();
+        let ver: String = en
+            .eval(r#"parse_json(`{"info": {"versions": ["1.0", "1.1"]}}`).info.versions[1]"#)
+            .unwrap();
+        assert_eq!(ver, "1.1");
+        let ver: String =
+            en.eval(r#"parse_toml("[tool]\nversion = \"0.4.8\"").tool.version"#).unwrap();
+        assert_eq!(ver, "0.4.8");
+        let n: i64 = en.eval("parse_json(`[1, 2, 3]`).len()").unwrap();
+        assert_eq!(n, 3);
+        assert!(en.run(r#"parse_json("{")"#).is_err());
+        assert!(en.run(r#"parse_toml("a = ")"#).is_err());
+    }
+}
@@ -1,5 +1,7 @@
 use rhai::CustomType;
 use std::{
+    collections::BTreeMap,
+    fmt::Write,
     fs,
     path::{Path, PathBuf},
 };
@@ -11,6 +13,166 @@ lazy_static::lazy_static! {
     static ref RE_DEFINE: regex::Regex = regex::Regex::new(r"(?m)%define(\s+)(\S+)(\s+)(\S+)$").unwrap();
     static ref RE_GLOBAL: regex::Regex = regex::Regex::new(r"(?m)%global(\s+)(\S+)(\s+)(\S+)$").unwrap();
     static ref RE_SOURCE: regex::Regex = regex::Regex::new(r"Source(\d+):(\s+)([^\n]+)\n").unwrap();
+    static ref RE_SOURCE_TAG: regex::Regex = regex::Regex::new(r"(?mi)^(Source|Patch)(\d*)[ \t]*:[ \t]*(\S.*?)[ \t]*$").unwrap();
+    static ref RE_CHANGELOG: regex::Regex = regex::Regex::new(r"(?m)^%changelog[ \t]*$\n?").unwrap();
+    static ref RE_DESCRIPTION: regex::Regex = regex::Regex::new(r"(?m)^%description[ \t]*$").unwrap();
+    static ref RE_CHANGELOG_HEADER: regex::Regex = regex::Regex::new(r"^\*\s+(\w+)\s+(\w+\s+\d+\s+\d+)\s+([^<]*?)\s*<([^>]*)>\s*(?:-\s*)?(.*?)\s*$").unwrap();
+    static ref RE_SECTION: regex::Regex = regex::Regex::new(r"^%(package|description|prep|generate_buildrequires|conf|build|install|check|clean|files|changelog|pre|post|preun|postun|pretrans|posttrans|verifyscript|\w*trigger\w*)\b").unwrap();
+}
+
+/// Preambles every spec file must define
+const REQUIRED_PREAMBLES: [&str; 5] = ["Name", "Version", "Release", "Summary", "License"];
+
+/// Problems found by [`RPMSpec::validate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// A required preamble such as `License:` is missing
+    MissingPreamble(&'static str),
+    /// There is no `%description` section for the main package
+    MissingDescription,
+    /// The `%description` section of the main package is empty
+    EmptyDescription,
+    /// An architecture is listed in both `ExclusiveArch:` and `ExcludeArch:`
+    ConflictingArch(String),
+    /// The package cannot be built for the target architecture, see [`RPMSpec::check_arch`]
+    ExcludedArch(String),
+    /// A `%changelog` entry header cannot be parsed
+    BadChangelog(String),
+    /// The weekday in a `%changelog` entry header does not match its date
+    ChangelogWeekday(String),
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingPreamble(p) => write!(f, "Missing required preamble `{p}:`"),
+            Self::MissingDescription => write!(f, "Missing `%description`"),
+            Self::EmptyDescription => write!(f, "`%description` is empty"),
+            Self::ConflictingArch(a) => {
+                write!(f, "`{a}` is listed in both `ExclusiveArch:` and `ExcludeArch:`")
+            }
+            Self::ExcludedArch(a) => write!(f, "The package cannot be built for `{a}`"),
+            Self::BadChangelog(h) => write!(f, "Invalid `%changelog` entry: `{h}`"),
+            Self::ChangelogWeekday(h) => {
+                write!(f, "Weekday does not match the date in `%changelog` entry: `{h}`")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// `Provides:` of a spec file, see [`RPMSpec::provides`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provides {
+    /// Values of the `Provides:` preambles by package name, see [`RPMSpec::packages`]
+    pub explicit: BTreeMap<String, Vec<String>>,
+    /// Whether rpm generates provides automatically (`AutoProv`/`AutoReqProv`, on by default)
+    ///
+    /// The automatic ones are not part of [`Provides::explicit`].
+    pub auto: bool,
+}
+
+/// A package defined in a spec file, see [`RPMSpec::packages`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    /// Full name of the package, e.g. `foo-devel` for `%package devel`
+    pub name: String,
+    pub summary: Option<String>,
+    /// Values of the `Requires:` preambles, without `BuildRequires:`
+    pub requires: Vec<String>,
+    /// Values of the `Provides:` preambles, without the automatic ones
+    pub provides: Vec<String>,
+}
+
+/// An entry of `%changelog`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Changelog {
+    pub date: chrono::NaiveDate,
+    pub maintainer: String,
+    pub email: String,
+    /// `version-release` after the email, e.g. `1.0-1`
+    pub version: Option<String>,
+    /// Lines after the header, usually starting with `- `
+    pub message: String,
+}
+
+impl Changelog {
+    /// Parse an entry header like `* Wed Jan 11 2006 Foo <foo@example.com> - 1.0-1`
+    ///
+    /// # Errors
+    /// - [`ValidationError::BadChangelog`] if the header is malformed
+    /// - [`ValidationError::ChangelogWeekday`] if the weekday does not match the date
+    pub fn parse_header(line: &str) -> Result<Self, ValidationError> {
+        let bad = || ValidationError::BadChangelog(line.to_owned());
+        let cap = RE_CHANGELOG_HEADER.captures(line).ok_or_else(bad)?;
+        let date = chrono::NaiveDate::parse_from_str(&cap[2], "%b %d %Y").map_err(|_| bad())?;
+        if !date.format("%a").to_string().eq_ignore_ascii_case(&cap[1]) {
+            return Err(ValidationError::ChangelogWeekday(line.to_owned()));
+        }
+        Ok(Self {
+            date,
+            maintainer: cap[3].to_owned(),
+            email: cap[4].to_owned(),
+            version: Some(cap[5].to_owned()).filter(|v| !v.is_empty()),
+            message: String::new(),
+        })
+    }
+    /// The canonical header line, e.g. `* Wed Jan 11 2006 Foo <foo@example.com> - 1.0-1`
+    #[must_use]
+    pub fn header(&self) -> String {
+        let mut h =
+            format!("* {} {} <{}>", self.date.format("%a %b %d %Y"), self.maintainer, self.email);
+        if let Some(v) = &self.version {
+            _ = write!(h, " - {v}");
+        }
+        h
+    }
+}
+
+/// Entries of `%changelog`, see [`RPMSpec::changelogs`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Changelogs {
+    /// Entries in the order they appear in the spec file
+    pub entries: Box<[Changelog]>,
+}
+
+impl Changelogs {
+    /// Parse the content of a `%changelog` section.
+    ///
+    /// # Errors
+    /// - an entry header is invalid, see [`Changelog::parse_header`]
+    pub fn parse(section: &str) -> Result<Self, ValidationError> {
+        let mut entries: Vec<Changelog> = vec![];
+        for line in section.lines() {
+            if line.starts_with('*') {
+                entries.push(Changelog::parse_header(line)?);
+            } else if let Some(entry) = entries.last_mut() {
+                entry.message.push_str(line);
+                entry.message.push('\n');
+            }
+        }
+        for entry in &mut entries {
+            entry.message.truncate(entry.message.trim_end().len());
+        }
+        Ok(Self { entries: entries.into() })
+    }
+    /// The most recent entry by date
+    ///
+    /// If several entries share the latest date, the one appearing first is returned.
+    #[must_use]
+    pub fn latest(&self) -> Option<&Changelog> {
+        self.entries.iter().rev().max_by_key(|c| c.date)
+    }
+    /// Sort the entries from newest to oldest, keeping the order of entries with the same date
+    pub fn sort_by_date(&mut self) {
+        self.entries.sort_by_key(|c| std::cmp::Reverse(c.date));
+    }
+}
+
+/// Split a dependency list like `foo >= 1.0, bar`.
+fn split_deps(v: &str) -> impl Iterator<Item = String> + '_ {
+    v.split(',').map(str::trim).filter(|d| !d.is_empty()).map(str::to_owned)
 }
 
 /// Update RPM spec files
@@ -86,6 +248,212 @@ impl RPMSpec {
         info!("{}: Source{i}: {p}", self.name);
         self.f = self.f.replace(&cap[0], &format!("Source{i}:{}{p}\n", &cap[2]));
     }
+    /// Get the value of the `Version:` preamble
+    #[must_use]
+    pub fn get_version(&self) -> Option<String> {
+        RE_VERSION.captures(&self.f).map(|m| m[2].to_owned())
+    }
+    /// `SourceN:` or `PatchN:` values by `N`, sorted by `N`. `Source:` without a number is `N = 0`.
+    fn numbered(&self, tag: &str) -> Vec<(u32, &str)> {
+        let mut v: Vec<_> = RE_SOURCE_TAG
+            .captures_iter(&self.f)
+            .filter(|cap| cap[1].eq_ignore_ascii_case(tag))
+            .filter_map(|cap| {
+                let n = cap.get(2)?.as_str();
+                Some((if n.is_empty() { 0 } else { n.parse().ok()? }, cap.get(3)?.as_str()))
+            })
+            .collect();
+        v.sort_by_key(|(n, _)| *n);
+        v
+    }
+    /// Values of the `SourceN:` preambles sorted by `N`
+    #[must_use]
+    pub fn sources_sorted(&self) -> Vec<(u32, &str)> {
+        self.numbered("Source")
+    }
+    /// Values of the `PatchN:` preambles sorted by `N`
+    #[must_use]
+    pub fn patches_sorted(&self) -> Vec<(u32, &str)> {
+        self.numbered("Patch")
+    }
+    /// Architectures listed in all `key:` preambles, e.g. `ExclusiveArch: x86_64 aarch64`
+    fn arches(&self, key: &str) -> Vec<&str> {
+        self.f
+            .lines()
+            .filter_map(|l| {
+                l.split_once(':').filter(|(k, _)| k.trim_end().eq_ignore_ascii_case(key))
+            })
+            .flat_map(|(_, v)| v.split([' ', '\t', ',']).filter(|a| !a.is_empty()))
+            .collect()
+    }
+    /// Check that the package can be built for `arch` according to `ExclusiveArch:` and
+    /// `ExcludeArch:`
+    ///
+    /// Macros are not expanded, so `ExclusiveArch:` is not checked if it uses any, e.g.
+    /// `%{rust_arches}`. Use the output of `rpmspec --parse` to check those.
+    #[must_use]
+    pub fn check_arch(&self, arch: &str) -> Option<ValidationError> {
+        let exclusive = self.arches("ExclusiveArch");
+        let unknown = exclusive.is_empty() || exclusive.iter().any(|a| a.contains('%'));
+        let excluded =
+            (!unknown && !exclusive.contains(&arch)) || self.arches("ExcludeArch").contains(&arch);
+        excluded.then(|| ValidationError::ExcludedArch(arch.to_owned()))
+    }
+    /// Lines of the main package's preamble, i.e. before the first section like `%package`
+    fn preamble(&self) -> impl Iterator<Item = &str> + Clone {
+        self.f.lines().take_while(|l| !RE_SECTION.is_match(l))
+    }
+    /// Check that the required preambles and `%description` are present
+    #[must_use]
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut errs: Vec<_> = REQUIRED_PREAMBLES
+            .into_iter()
+            .filter(|p| {
+                !self.preamble().any(|l| {
+                    l.split_once(':').is_some_and(|(k, v)| {
+                        k.trim_end().eq_ignore_ascii_case(p) && !v.trim().is_empty()
+                    })
+                })
+            })
+            .map(ValidationError::MissingPreamble)
+            .collect();
+        let excluded = self.arches("ExcludeArch");
+        let mut conflicts: Vec<_> =
+            self.arches("ExclusiveArch").into_iter().filter(|a| excluded.contains(a)).collect();
+        conflicts.dedup();
+        errs.extend(conflicts.into_iter().map(|a| ValidationError::ConflictingArch(a.to_owned())));
+        match RE_DESCRIPTION.find(&self.f) {
+            None => errs.push(ValidationError::MissingDescription),
+            Some(m) => {
+                let mut body = self.f[m.end()..].lines().take_while(|l| !RE_SECTION.is_match(l));
+                if body.all(|l| l.trim().is_empty()) {
+                    errs.push(ValidationError::EmptyDescription);
+                }
+            }
+        }
+        if let Some(m) = RE_CHANGELOG.find(&self.f) {
+            let headers = self.f[m.end()..].lines().filter(|l| l.starts_with('*'));
+            errs.extend(headers.filter_map(|l| Changelog::parse_header(l).err()));
+        }
+        errs
+    }
+    /// Get the explicit `Provides:` and whether automatic provides are enabled
+    #[must_use]
+    pub fn provides(&self) -> Provides {
+        let last = |key: &str| {
+            self.f
+                .lines()
+                .filter_map(|l| {
+                    let (k, v) = l.split_once(':')?;
+                    k.trim_end().eq_ignore_ascii_case(key).then(|| v.trim().to_ascii_lowercase())
+                })
+                .next_back()
+        };
+        // `AutoProv:` takes precedence over `AutoReqProv:`
+        let auto = last("AutoProv")
+            .or_else(|| last("AutoReqProv"))
+            .is_none_or(|v| !matches!(v.as_str(), "no" | "0"));
+        let explicit = self.packages().into_iter().map(|p| (p.name, p.provides)).collect();
+        Provides { explicit, auto }
+    }
+    /// List the main package and its subpackages with their summaries and requirements
+    #[must_use]
+    pub fn packages(&self) -> Vec<Package> {
+        let new = |name| Package { name, summary: None, requires: vec![], provides: vec![] };
+        let mut pkgs = vec![new(String::new())];
+        let mut in_preamble = true;
+        for line in self.f.lines() {
+            if RE_SECTION.is_match(line) {
+                in_preamble = false;
+                let mut args = line.split_whitespace();
+                if args.next() != Some("%package") {
+                    continue;
+                }
+                let name = match args.next() {
+                    Some("-n") => args.next().unwrap_or_default().to_owned(),
+                    Some(suffix) => {
+                        format!("{}-{suffix}", pkgs.first().map_or("", |p| p.name.as_str()))
+                    }
+                    None => continue,
+                };
+                pkgs.push(new(name));
+                in_preamble = true;
+                continue;
+            }
+            let Some((k, v)) = line.split_once(':').filter(|_| in_preamble) else { continue };
+            let Some(pkg) = pkgs.last_mut() else { continue };
+            let (k, v) = (k.trim_end(), v.trim());
+            if k.eq_ignore_ascii_case("Name") {
+                v.clone_into(&mut pkg.name);
+            } else if k.eq_ignore_ascii_case("Summary") {
+                pkg.summary = Some(v.to_owned());
+            } else if k.eq_ignore_ascii_case("Requires")
+                || k.to_ascii_lowercase().starts_with("requires(")
+            {
+                pkg.requires.extend(split_deps(v));
+            } else if k.eq_ignore_ascii_case("Provides") {
+                pkg.provides.extend(split_deps(v));
+            }
+        }
+        pkgs
+    }
+    /// Describe each package of the spec, see [`RPMSpec::packages`]
+    #[must_use]
+    pub fn summary_report(&self) -> String {
+        let mut out = String::new();
+        for pkg in self.packages() {
+            _ = writeln!(out, "{}: {}", pkg.name, pkg.summary.as_deref().unwrap_or("(no summary)"));
+            if !pkg.requires.is_empty() {
+                _ = writeln!(out, "  Requires: {}", pkg.requires.join(", "));
+            }
+        }
+        out
+    }
+    /// Parse the entries of `%changelog`, which are empty if the section is missing
+    ///
+    /// # Errors
+    /// - an entry header is invalid, see [`Changelog::parse_header`]
+    pub fn changelogs(&self) -> Result<Changelogs, ValidationError> {
+        RE_CHANGELOG.find(&self.f).map_or_else(
+            || Ok(Changelogs { entries: Box::new([]) }),
+            |m| Changelogs::parse(&self.f[m.end()..]),
+        )
+    }
+    /// Add an entry for today to the top of `%changelog`, creating the section if needed
+    pub fn add_changelog(&mut self, message: &str, name: &str, email: &str, version: &str) {
+        let date = chrono::Utc::now().date_naive();
+        self.add_changelog_at(date, message, name, email, version);
+    }
+    fn add_changelog_at(
+        &mut self,
+        date: chrono::NaiveDate,
+        message: &str,
+        name: &str,
+        email: &str,
+        version: &str,
+    ) {
+        let header = Changelog {
+            date,
+            maintainer: name.to_owned(),
+            email: email.to_owned(),
+            version: Some(version.to_owned()),
+            message: String::new(),
+        }
+        .header();
+        let entry = format!("{header}\n- {message}\n\n");
+        if let Some(m) = RE_CHANGELOG.find(&self.f) {
+            // `%changelog` may be the last line without a newline
+            let sep = if m.as_str().ends_with('\n') { "" } else { "\n" };
+            self.f.insert_str(m.end(), &format!("{sep}{entry}"));
+        } else {
+            if !self.f.ends_with('\n') {
+                self.f.push('\n');
+            }
+            self.f.push_str("\n%changelog\n");
+            self.f.push_str(entry.trim_end_matches('\n'));
+            self.f.push('\n');
+        }
+    }
     /// Write the updated spec file content
     ///
     /// # Errors
@@ -122,6 +490,178 @@ impl CustomType for RPMSpec {
             .with_fn("release", Self::reset_release)
             .with_fn("release", Self::release)
             .with_fn("changed", Self::changed)
+            .with_fn("add_changelog", Self::add_changelog)
+            .with_fn("validate", |rpm: &mut Self| -> rhai::Array {
+                rpm.validate().into_iter().map(|e| e.to_string().into()).collect()
+            })
+            .with_fn("summary_report", |rpm: &mut Self| rpm.summary_report())
+            .with_fn("provides", |rpm: &mut Self| -> rhai::Map {
+                let Provides { explicit, auto } = rpm.provides();
+                let explicit: rhai::Map = explicit
+                    .into_iter()
+                    .map(|(name, p)| {
+                        (name.into(), p.into_iter().map(Into::into).collect::<rhai::Array>().into())
+                    })
+                    .collect();
+                rhai::Map::from([
+                    ("explicit".into(), explicit.into()),
+                    ("auto".into(), auto.into()),
+                ])
+            })
             .with_get_set("f", Self::get, Self::set);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spec(f: &str) -> RPMSpec {
+        RPMSpec {
+            original: f.to_owned(),
+            name: "foo".to_owned(),
+            chkupdate: PathBuf::from("update.rhai"),
+            spec: PathBuf::from("foo.spec"),
+            f: f.to_owned(),
+        }
+    }
+
+    #[test]
+    fn validate() {
+        let f = "Name: foo\nVersion: 1.0\nRelease: 1%?dist\nSummary: Foo\n\n%description\nFoo.\n\n%prep\n";
+        assert_eq!(spec(f).validate(), [ValidationError::MissingPreamble("License")]);
+
+        let f = "Name: foo\nVersion: 1.0\nRelease: 1%?dist\nSummary: Foo\nLicense: MIT\n\n%description\n\n%prep\n";
+        assert_eq!(spec(f).validate(), [ValidationError::EmptyDescription]);
+
+        // preambles of subpackages do not count for the main package
+        let f = "Name: foo\nVersion: 1.0\nRelease: 1%?dist\nSummary: Foo\n\n%description\nFoo.\n\n%package devel\nSummary: Foo headers\nLicense: MIT\n";
+        assert_eq!(spec(f).validate(), [ValidationError::MissingPreamble("License")]);
+    }
+
+    #[test]
+    fn arches() {
+        let f = "Name: foo\nVersion: 1.0\nRelease: 1%?dist\nSummary: Foo\nLicense: MIT\n\
+            ExclusiveArch: x86_64 aarch64\nExcludeArch: aarch64, ppc64le\n\n%description\nFoo.\n";
+        let rpm = spec(f);
+        assert_eq!(rpm.validate(), [ValidationError::ConflictingArch("aarch64".to_owned())]);
+        assert_eq!(rpm.check_arch("x86_64"), None);
+        assert_eq!(
+            rpm.check_arch("aarch64"),
+            Some(ValidationError::ExcludedArch("aarch64".to_owned()))
+        );
+        assert!(rpm.check_arch("s390x").is_some());
+        assert_eq!(spec("Name: foo\n").check_arch("s390x"), None);
+        assert_eq!(spec("Name: foo\nExclusiveArch: %{ix86} x86_64\n").check_arch("i686"), None);
+    }
+
+    #[test]
+    fn add_changelog() {
+        let mut rpm = spec(
+            "Name: foo\n\n%changelog\n* Tue Jan 10 2006 Foo <foo@example.com> - 0.9-1\n- Old\n",
+        );
+        let date = chrono::NaiveDate::from_ymd_opt(2006, 1, 11).unwrap();
+        rpm.add_changelog_at(date, "Update to 1.0", "Foo", "foo@example.com", "1.0-1");
+        assert_eq!(
+            rpm.f,
+            "Name: foo\n\n%changelog\n* Wed Jan 11 2006 Foo <foo@example.com> - 1.0-1\n- Update to 1.0\n\n* Tue Jan 10 2006 Foo <foo@example.com> - 0.9-1\n- Old\n"
+        );
+
+        let mut rpm = spec("Name: foo\n");
+        rpm.add_changelog("Init", "Foo", "foo@example.com", "1.0-1");
+        let header = rpm.f.lines().find_map(|l| l.strip_prefix("* ")).unwrap();
+        // parsing fails if the weekday does not match the date
+        let date = header.splitn(5, ' ').take(4).collect::<Vec<_>>().join(" ");
+        chrono::NaiveDate::parse_from_str(&date, "%a %b %d %Y").unwrap();
+        assert!(rpm.f.starts_with("Name: foo\n\n%changelog\n* "), "{}", rpm.f);
+        assert!(rpm.f.ends_with(" Foo <foo@example.com> - 1.0-1\n- Init\n"), "{}", rpm.f);
+    }
+
+    #[test]
+    fn changelog_header() {
+        let header = "* Wed Jan 11 2006 Foo Bar <foo@example.com> - 1.0-1";
+        let c = Changelog::parse_header(header).unwrap();
+        assert_eq!(c.date, chrono::NaiveDate::from_ymd_opt(2006, 1, 11).unwrap());
+        assert_eq!((c.maintainer.as_str(), c.email.as_str()), ("Foo Bar", "foo@example.com"));
+        assert_eq!(c.version.as_deref(), Some("1.0-1"));
+        assert_eq!(c.header(), header);
+        let c = Changelog::parse_header("* Wed Jan 11 2006 Foo <foo@example.com>").unwrap();
+        assert_eq!(c.header(), "* Wed Jan 11 2006 Foo <foo@example.com>");
+
+        let header = "* Tue Jan 11 2006 Foo <foo@example.com> - 1.0-1";
+        let err = ValidationError::ChangelogWeekday(header.to_owned());
+        assert_eq!(Changelog::parse_header(header), Err(err.clone()));
+        let f = format!("Name: foo\nVersion: 1.0\nRelease: 1%?dist\nSummary: Foo\nLicense: MIT\n\n%description\nFoo.\n\n%changelog\n{header}\n- Init\n");
+        assert_eq!(spec(&f).validate(), [err]);
+        Changelog::parse_header("* Foo <foo@example.com>").unwrap_err();
+    }
+
+    #[test]
+    fn changelogs() {
+        let rpm = spec(
+            "Name: foo\n\n%changelog\n\
+            * Tue Jan 10 2006 Foo <foo@example.com> - 0.9-1\n- Old\n\n\
+            * Wed Jan 11 2006 Foo <foo@example.com> - 1.0-1\n- New\n- Really\n\n\
+            * Mon Jan 09 2006 Foo <foo@example.com> - 0.8-1\n- Older\n",
+        );
+        let mut logs = rpm.changelogs().unwrap();
+        let latest = logs.latest().unwrap();
+        assert_eq!(latest.version.as_deref(), Some("1.0-1"));
+        assert_eq!(latest.message, "- New\n- Really");
+        logs.sort_by_date();
+        let versions: Vec<_> = logs.entries.iter().filter_map(|c| c.version.as_deref()).collect();
+        assert_eq!(versions, ["1.0-1", "0.9-1", "0.8-1"]);
+        assert!(spec("Name: foo\n").changelogs().unwrap().latest().is_none());
+    }
+
+    #[test]
+    fn sources_sorted() {
+        let rpm = spec(
+            "Name: foo\nSource10: foo.conf\nSource: https://example.com/foo.tar.gz\n\
+            Patch2: b.patch\nSource2: foo.service\npatch1: a.patch\n",
+        );
+        assert_eq!(
+            rpm.sources_sorted(),
+            [(0, "https://example.com/foo.tar.gz"), (2, "foo.service"), (10, "foo.conf")]
+        );
+        assert_eq!(rpm.patches_sorted(), [(1, "a.patch"), (2, "b.patch")]);
+    }
+
+    #[test]
+    fn summary_report() {
+        let f = "Name: foo\nSummary: Foo tool\nRequires: bar\n\n%description\nFoo.\n\n\
+            %package devel\nSummary: Foo headers\nRequires: %{name} = %{version}-%{release}, pkgconfig\n\n\
+            %description devel\nHeaders.\n\n%package -n python3-foo\nSummary: Python bindings\n\n\
+            %files\n";
+        let pkgs = spec(f).packages();
+        let names: Vec<_> = pkgs.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["foo", "foo-devel", "python3-foo"]);
+        assert_eq!(pkgs[1].requires, ["%{name} = %{version}-%{release}", "pkgconfig"]);
+        assert_eq!(
+            spec(f).summary_report(),
+            "foo: Foo tool\n  Requires: bar\nfoo-devel: Foo headers\n  Requires: %{name} = %{version}-%{release}, pkgconfig\npython3-foo: Python bindings\n"
+        );
+    }
+
+    #[test]
+    fn provides() {
+        let f = "Name: foo\nProvides: bar = 1.0, baz\nProvides: qux\n\n%description\nFoo.\n";
+        let p = spec(f).provides();
+        assert!(p.auto);
+        assert_eq!(p.explicit["foo"], ["bar = 1.0", "baz", "qux"]);
+
+        let p = spec("Name: foo\nAutoReqProv: no\nProvides: bar\n").provides();
+        assert!(!p.auto);
+        assert_eq!(p.explicit["foo"], ["bar"]);
+
+        let f = "Name: foo\nProvides: bar\n\n%description\nFoo.\n\n\
+            %package devel\nSummary: Foo headers\nProvides: bar-devel = 1.0\n\n\
+            %package -n python3-foo\nSummary: Python bindings\n";
+        let p = spec(f).provides();
+        assert_eq!(p.explicit["foo"], ["bar"]);
+        assert_eq!(p.explicit["foo-devel"], ["bar-devel = 1.0"]);
+        assert!(p.explicit["python3-foo"].is_empty());
+
+        assert!(spec("AutoReqProv: no\nAutoProv: yes\n").provides().auto);
+    }
+}
@@ -1,6 +1,6 @@
 /// Tenshi module for `AndaX`
 /// Various utility functions for Andaman Scripting
-use crate::error::AndaxRes;
+use crate::{error::AndaxRes, run::rf};
 
 use rhai::{
     plugin::{
@@ -11,6 +11,47 @@ use rhai::{
 };
 type Res<T = ()> = Result<T, Box<RhaiE>>;
 
+/// Whether `ANDA_DRY_RUN` is set to anything other than `0` or an empty string.
+///
+/// In dry-run mode, the line editing functions only count the matching lines.
+fn dry_run() -> bool {
+    std::env::var("ANDA_DRY_RUN").is_ok_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Call `edit` with each line of `path` matching the regex `matcher` and the lines to write
+/// instead, keeping other lines as-is.
+///
+/// Returns the number of matching lines. The file is only written if there are any matches
+/// and `dry_run` is false.
+#[allow(clippy::arithmetic_side_effects)]
+fn edit_lines<F: FnMut(&str, &mut Vec<String>)>(
+    path: &str,
+    matcher: &str,
+    dry_run: bool,
+    mut edit: F,
+) -> color_eyre::Result<i64> {
+    let re = regex::Regex::new(matcher)?;
+    let content = std::fs::read_to_string(path)?;
+    let (mut lines, mut count) = (vec![], 0);
+    for line in content.lines() {
+        if re.is_match(line) {
+            count += 1;
+            edit(line, &mut lines);
+        } else {
+            lines.push(line.to_owned());
+        }
+    }
+    if count == 0 || dry_run {
+        return Ok(count);
+    }
+    let mut out = lines.join("\n");
+    if content.ends_with('\n') {
+        out.push('\n');
+    }
+    std::fs::write(path, out)?;
+    Ok(count)
+}
+
 #[export_module]
 pub mod ar {
     use hcl::eval::Evaluate;
@@ -78,4 +119,89 @@ pub mod ar {
         let map = serde_json::from_str(&json).ehdl(&ctx)?;
         Ok(map)
     }
+
+    /// replace each line in the file matching the regex `matcher` with `newline`
+    ///
+    /// Returns the number of replaced lines.
+    /// ## Example
+    /// ```rhai
+    /// replace_line("foo.conf", "^version=", `version=${ver}`);
+    /// ```
+    #[rhai_fn(return_raw, global)]
+    pub fn replace_line(
+        ctx: NativeCallContext,
+        path: &str,
+        matcher: &str,
+        newline: &str,
+    ) -> Res<i64> {
+        rf(&ctx, edit_lines(path, matcher, dry_run(), |_, out| out.push(newline.to_owned())))
+    }
+
+    /// insert `line` after each line in the file matching the regex `matcher`
+    ///
+    /// Returns the number of matching lines.
+    #[rhai_fn(return_raw, global)]
+    pub fn insert_after(ctx: NativeCallContext, path: &str, matcher: &str, line: &str) -> Res<i64> {
+        rf(
+            &ctx,
+            edit_lines(path, matcher, dry_run(), |old, out| {
+                out.push(old.to_owned());
+                out.push(line.to_owned());
+            }),
+        )
+    }
+
+    /// delete each line in the file matching the regex `matcher`
+    ///
+    /// Returns the number of deleted lines.
+    #[rhai_fn(return_raw, global)]
+    pub fn delete_line(ctx: NativeCallContext, path: &str, matcher: &str) -> Res<i64> {
+        rf(&ctx, edit_lines(path, matcher, dry_run(), |_, _| {}))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run_on(content: &str, script: &str) -> (i64, String) {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(f.path(), content).unwrap();
+        let (en, mut sc) = crate::run::gen_en();
+        sc.push("path", f.path().to_string_lossy().to_string());
+        let n = en.eval_with_scope(&mut sc, script).unwrap();
+        (n, std::fs::read_to_string(f.path()).unwrap())
+    }
+
+    #[test]
+    fn replace_line() {
+        let (n, out) = run_on("a=1\nb=2\na=3\n", r#"replace_line(path, "^a=", "a=0")"#);
+        assert_eq!(n, 2);
+        assert_eq!(out, "a=0\nb=2\na=0\n");
+    }
+
+    #[test]
+    fn insert_after() {
+        let (n, out) = run_on("[main]\nfoo=1", r#"insert_after(path, "^\\[main\\]$", "bar=2")"#);
+        assert_eq!(n, 1);
+        assert_eq!(out, "[main]\nbar=2\nfoo=1");
+    }
+
+    #[test]
+    fn delete_line() {
+        let (n, out) = run_on("a\n# comment\nb\n", r#"delete_line(path, "^#")"#);
+        assert_eq!(n, 1);
+        assert_eq!(out, "a\nb\n");
+        let (n, out) = run_on("a\n", r#"delete_line(path, "^#")"#);
+        assert_eq!((n, out.as_str()), (0, "a\n"));
+    }
+
+    #[test]
+    fn dry_run() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(f.path(), "a\na\n").unwrap();
+        let path = f.path().to_str().unwrap();
+        assert_eq!(edit_lines(path, "a", true, |_, _| {}).unwrap(), 2);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "a\na\n");
+    }
 }
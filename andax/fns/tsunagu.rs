@@ -7,49 +7,149 @@ use rhai::{
     CustomType, FuncRegistration,
 };
 use serde_json::Value;
-use std::env::VarError;
+use std::{env::VarError, time::Duration};
 use tracing::trace;
 
 type Res<T> = Result<T, Box<EvalAltResult>>;
 
 pub const USER_AGENT: &str = "AndaX";
+/// Number of redirects followed unless specified otherwise.
+const DEFAULT_REDIRECTS: u32 = 5;
+
+#[derive(Debug)]
+pub enum NetError {
+    /// `ANDA_OFFLINE` is set
+    Offline,
+    /// `ANDA_HTTP_TIMEOUT` is not a number of seconds
+    BadTimeout(String),
+    Ureq(ureq::Error),
+}
+
+impl std::fmt::Display for NetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Offline => write!(f, "Network access is disabled (ANDA_OFFLINE is set)"),
+            Self::BadTimeout(t) => write!(f, "Invalid ANDA_HTTP_TIMEOUT: `{t}` (expected seconds)"),
+            Self::Ureq(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for NetError {}
+
+impl From<ureq::Error> for NetError {
+    fn from(e: ureq::Error) -> Self {
+        Self::Ureq(e)
+    }
+}
+
+/// Whether offline mode is enabled, i.e. `ANDA_OFFLINE` is set to anything other than `0` or an
+/// empty string.
+#[must_use]
+pub fn offline() -> bool {
+    std::env::var("ANDA_OFFLINE").is_ok_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Create the HTTP agent used by all network functions in AndaX.
+///
+/// If `offline` is true (usually [`offline()`]), this fails immediately.
+/// The agent honours the following environment variables:
+/// - `ANDA_PROXY`: proxy URL, falls back to `ALL_PROXY`, `HTTPS_PROXY` and `HTTP_PROXY`
+/// - `ANDA_HTTP_TIMEOUT`: timeout of each request in seconds
+///
+/// # Errors
+/// - offline mode is enabled
+/// - the proxy or timeout settings are invalid
+pub fn agent(redirects: u32, offline: bool) -> Result<ureq::Agent, NetError> {
+    if offline {
+        return Err(NetError::Offline);
+    }
+    let proxy = match std::env::var("ANDA_PROXY") {
+        Ok(p) => Some(ureq::Proxy::new(&p)?),
+        Err(_) => ureq::Proxy::try_from_env(),
+    };
+    let timeout = match std::env::var("ANDA_HTTP_TIMEOUT") {
+        Ok(t) => Some(Duration::from_secs(t.parse().map_err(|_| NetError::BadTimeout(t))?)),
+        Err(_) => None,
+    };
+    trace!(?proxy, ?timeout, redirects, "Creating HTTP agent");
+    Ok(ureq::Agent::config_builder()
+        .user_agent(USER_AGENT)
+        .proxy(proxy)
+        .timeout_global(timeout)
+        .max_redirects(redirects)
+        .max_redirects_will_error(false)
+        .build()
+        .into())
+}
+
+/// Create a GET request using [`agent()`].
+///
+/// # Errors
+/// See [`agent()`].
+pub fn get_req(url: &str) -> Result<ureq::RequestBuilder<ureq::typestate::WithoutBody>, NetError> {
+    Ok(agent(DEFAULT_REDIRECTS, offline())?.get(url))
+}
+
+/// Download `url` into the file `dest` using [`agent()`].
+///
+/// # Errors
+/// - see [`agent()`]
+/// - the request fails
+/// - `dest` cannot be written to
+pub fn download(url: &str, dest: &std::path::Path, offline: bool) -> color_eyre::Result<()> {
+    let mut body = agent(DEFAULT_REDIRECTS, offline)?.get(url).call()?.into_body();
+    let mut file = std::fs::File::create(dest)?;
+    std::io::copy(&mut body.as_reader(), &mut file)?;
+    Ok(())
+}
+
+/// Latest stable version in a response of Anitya's `/api/v2/versions/` endpoint.
+///
+/// `stable_versions` is sorted from newest to oldest. Falls back to `latest_version` if the
+/// project has no stable versions.
+fn anitya_version(v: &Value) -> Option<&str> {
+    v["stable_versions"].get(0).unwrap_or_else(|| &v["latest_version"]).as_str()
+}
+
 #[export_module]
 pub mod ar {
     type E = Box<rhai::EvalAltResult>;
 
     #[rhai_fn(return_raw, global)]
     pub fn get(ctx: NativeCallContext, url: &str) -> Res<String> {
-        ureq::AgentBuilder::new()
-            .redirects(0)
-            .build()
+        agent(0, offline())
+            .ehdl(&ctx)?
             .get(url)
-            .set("User-Agent", USER_AGENT)
             .call()
             .ehdl(&ctx)?
-            .into_string()
+            .into_body()
+            .read_to_string()
             .ehdl(&ctx)
     }
 
     #[rhai_fn(return_raw, global)]
     pub fn gh(ctx: NativeCallContext, repo: &str) -> Res<String> {
-        let v: Value = ureq::get(&format!("https://api.github.com/repos/{repo}/releases/latest"))
-            .set("Authorization", &format!("Bearer {}", internal_env("GITHUB_TOKEN")?))
-            .set("User-Agent", USER_AGENT)
+        let v: Value = get_req(&format!("https://api.github.com/repos/{repo}/releases/latest"))
+            .ehdl(&ctx)?
+            .header("Authorization", &format!("Bearer {}", internal_env("GITHUB_TOKEN")?))
             .call()
             .ehdl(&ctx)?
-            .into_json()
+            .into_body()
+            .read_json()
             .ehdl(&ctx)?;
         trace!("Got json from {repo}:\n{v}");
         Ok(v["tag_name"].as_str().unwrap_or("").to_owned())
     }
     #[rhai_fn(return_raw, global)]
     pub fn gh_tag(ctx: NativeCallContext, repo: &str) -> Res<String> {
-        let v: Value = ureq::get(&format!("https://api.github.com/repos/{repo}/tags"))
-            .set("Authorization", &format!("Bearer {}", internal_env("GITHUB_TOKEN")?))
-            .set("User-Agent", USER_AGENT)
+        let v: Value = get_req(&format!("https://api.github.com/repos/{repo}/tags"))
+            .ehdl(&ctx)?
+            .header("Authorization", &format!("Bearer {}", internal_env("GITHUB_TOKEN")?))
             .call()
             .ehdl(&ctx)?
-            .into_json()
+            .into_body()
+            .read_json()
             .ehdl(&ctx)?;
         trace!("Got json from {repo}:\n{v}");
         let v = v
@@ -60,33 +160,36 @@ pub mod ar {
     }
     #[rhai_fn(return_raw, global)]
     pub fn gh_commit(ctx: NativeCallContext, repo: &str) -> Res<String> {
-        let v: Value = ureq::get(&format!("https://api.github.com/repos/{repo}/commits/HEAD"))
-            .set("Authorization", &format!("Bearer {}", internal_env("GITHUB_TOKEN")?))
-            .set("User-Agent", USER_AGENT)
+        let v: Value = get_req(&format!("https://api.github.com/repos/{repo}/commits/HEAD"))
+            .ehdl(&ctx)?
+            .header("Authorization", &format!("Bearer {}", internal_env("GITHUB_TOKEN")?))
             .call()
             .ehdl(&ctx)?
-            .into_json()
+            .into_body()
+            .read_json()
             .ehdl(&ctx)?;
         trace!("Got json from {repo}:\n{v}");
         Ok(v["sha"].as_str().unwrap_or("").to_owned())
     }
     #[rhai_fn(return_raw, global)]
     pub fn gh_rawfile(ctx: NativeCallContext, repo: &str, branch: &str, file: &str) -> Res<String> {
-        ureq::get(&format!("https://raw.githubusercontent.com/{repo}/{branch}/{file}"))
-            .set("User-Agent", USER_AGENT)
+        get_req(&format!("https://raw.githubusercontent.com/{repo}/{branch}/{file}"))
+            .ehdl(&ctx)?
             .call()
             .ehdl(&ctx)?
-            .into_string()
+            .into_body()
+            .read_to_string()
             .ehdl(&ctx)
     }
 
     #[rhai_fn(return_raw, name = "gitlab", global)]
     pub fn gitlab_domain(ctx: NativeCallContext, domain: &str, id: &str) -> Res<String> {
-        let v: Value = ureq::get(&format!("https://{domain}/api/v4/projects/{id}/releases/"))
-            .set("User-Agent", USER_AGENT)
+        let v: Value = get_req(&format!("https://{domain}/api/v4/projects/{id}/releases/"))
+            .ehdl(&ctx)?
             .call()
             .ehdl(&ctx)?
-            .into_json()
+            .into_body()
+            .read_json()
             .ehdl(&ctx)?;
         trace!("Got json from {id}:\n{v}");
         Ok(v[0]["tag_name"].as_str().unwrap_or("").to_owned())
@@ -97,11 +200,12 @@ pub mod ar {
     }
     #[rhai_fn(return_raw, name = "gitlab_tag", global)]
     pub fn gitlab_tag_domain(ctx: NativeCallContext, domain: &str, id: &str) -> Res<String> {
-        let v: Value = ureq::get(&format!("https://{domain}/api/v4/projects/{id}/repository/tags"))
-            .set("User-Agent", USER_AGENT)
+        let v: Value = get_req(&format!("https://{domain}/api/v4/projects/{id}/repository/tags"))
+            .ehdl(&ctx)?
             .call()
             .ehdl(&ctx)?
-            .into_json()
+            .into_body()
+            .read_json()
             .ehdl(&ctx)?;
         trace!("Got json from {id}:\n{v}");
         Ok(v[0]["name"].as_str().unwrap_or("").to_owned())
@@ -117,14 +221,14 @@ pub mod ar {
         id: &str,
         branch: &str,
     ) -> Res<String> {
-        let v: Value = ureq::get(&format!(
-            "https://{domain}/api/v4/projects/{id}/repository/branches/{branch}"
-        ))
-        .set("User-Agent", USER_AGENT)
-        .call()
-        .ehdl(&ctx)?
-        .into_json()
-        .ehdl(&ctx)?;
+        let v: Value =
+            get_req(&format!("https://{domain}/api/v4/projects/{id}/repository/branches/{branch}"))
+                .ehdl(&ctx)?
+                .call()
+                .ehdl(&ctx)?
+                .into_body()
+                .read_json()
+                .ehdl(&ctx)?;
         trace!("Got json from {id}:\n{v}");
         Ok(v["commit"]["id"].as_str().unwrap_or("").to_owned())
     }
@@ -135,9 +239,8 @@ pub mod ar {
 
     #[rhai_fn(return_raw, global)]
     pub fn pypi(ctx: NativeCallContext, name: &str) -> Res<String> {
-        let obj = ureq::get(&format!("https://pypi.org/pypi/{name}/json"));
-        let obj: serde_json::Value =
-            obj.set("User-Agent", USER_AGENT).call().ehdl(&ctx)?.into_json().ehdl(&ctx)?;
+        let obj = get_req(&format!("https://pypi.org/pypi/{name}/json")).ehdl(&ctx)?;
+        let obj: serde_json::Value = obj.call().ehdl(&ctx)?.into_body().read_json().ehdl(&ctx)?;
         let obj = obj.get("info").ok_or_else(|| E::from("No json[`info`]?"))?;
         let obj = obj.get("version").ok_or_else(|| E::from("No json[`info`][`version`]?"))?;
         obj.as_str().map(std::string::ToString::to_string).ok_or_else(|| "json not string?".into())
@@ -145,9 +248,8 @@ pub mod ar {
 
     #[rhai_fn(return_raw, global)]
     pub fn crates(ctx: NativeCallContext, name: &str) -> Res<String> {
-        let obj = ureq::get(&format!("https://crates.io/api/v1/crates/{name}"));
-        let obj: serde_json::Value =
-            obj.set("User-Agent", USER_AGENT).call().ehdl(&ctx)?.into_json().ehdl(&ctx)?;
+        let obj = get_req(&format!("https://crates.io/api/v1/crates/{name}")).ehdl(&ctx)?;
+        let obj: serde_json::Value = obj.call().ehdl(&ctx)?.into_body().read_json().ehdl(&ctx)?;
         let obj = obj.get("crate").ok_or_else(|| E::from("No json[`crate`]?"))?;
         let obj = obj.get("max_stable_version");
         let obj = obj.ok_or_else(|| E::from("No json[`crate`][`max_stable_version`]?"))?;
@@ -156,9 +258,8 @@ pub mod ar {
 
     #[rhai_fn(return_raw, global)]
     pub fn crates_max(ctx: NativeCallContext, name: &str) -> Res<String> {
-        let obj = ureq::get(&format!("https://crates.io/api/v1/crates/{name}"));
-        let obj: serde_json::Value =
-            obj.set("User-Agent", USER_AGENT).call().ehdl(&ctx)?.into_json().ehdl(&ctx)?;
+        let obj = get_req(&format!("https://crates.io/api/v1/crates/{name}")).ehdl(&ctx)?;
+        let obj: serde_json::Value = obj.call().ehdl(&ctx)?.into_body().read_json().ehdl(&ctx)?;
         let obj = obj.get("crate").ok_or_else(|| E::from("No json[`crate`]?"))?;
         let obj = obj.get("max_version");
         let obj = obj.ok_or_else(|| E::from("No json[`crate`][`max_version`]?"))?;
@@ -167,19 +268,27 @@ pub mod ar {
 
     #[rhai_fn(return_raw, global)]
     pub fn crates_newest(ctx: NativeCallContext, name: &str) -> Res<String> {
-        let obj = ureq::get(&format!("https://crates.io/api/v1/crates/{name}"));
-        let obj: serde_json::Value =
-            obj.set("User-Agent", USER_AGENT).call().ehdl(&ctx)?.into_json().ehdl(&ctx)?;
+        let obj = get_req(&format!("https://crates.io/api/v1/crates/{name}")).ehdl(&ctx)?;
+        let obj: serde_json::Value = obj.call().ehdl(&ctx)?.into_body().read_json().ehdl(&ctx)?;
         let obj = obj.get("crate").ok_or_else(|| E::from("No json[`crate`]?"))?;
         let obj = obj.get("newest_version");
         let obj = obj.ok_or_else(|| E::from("No json[`crate`][`newest_version`]?"))?;
         obj.as_str().map(std::string::ToString::to_string).ok_or_else(|| "json not string?".into())
     }
+    /// latest stable version of a project on release-monitoring.org (Anitya)
+    #[rhai_fn(return_raw, global)]
+    pub fn anitya_latest(ctx: NativeCallContext, project_id: i64) -> Res<String> {
+        let url =
+            format!("https://release-monitoring.org/api/v2/versions/?project_id={project_id}");
+        let v: Value =
+            get_req(&url).ehdl(&ctx)?.call().ehdl(&ctx)?.into_body().read_json().ehdl(&ctx)?;
+        trace!("Got json from anitya {project_id}:\n{v}");
+        anitya_version(&v).map(str::to_owned).ok_or_else(|| E::from("anitya: no versions"))
+    }
     #[rhai_fn(return_raw, global)]
     pub fn npm(ctx: NativeCallContext, name: &str) -> Res<String> {
-        let obj = ureq::get(&format!("https://registry.npmjs.org/{name}/latest"));
-        let obj: serde_json::Value =
-            obj.set("User-Agent", USER_AGENT).call().ehdl(&ctx)?.into_json().ehdl(&ctx)?;
+        let obj = get_req(&format!("https://registry.npmjs.org/{name}/latest")).ehdl(&ctx)?;
+        let obj: serde_json::Value = obj.call().ehdl(&ctx)?.into_body().read_json().ehdl(&ctx)?;
         let obj = obj.get("version").ok_or_else(|| E::from("No json[`version`]?"))?;
         obj.as_str().map(std::string::ToString::to_string).ok_or_else(|| "json not string?".into())
     }
@@ -224,13 +333,11 @@ impl Req {
         Self { url, headers: vec![], redirects: 0 }
     }
     pub fn get(self) -> color_eyre::Result<String> {
-        let r =
-            ureq::AgentBuilder::new().redirects(self.redirects.try_into()?).build().get(&self.url);
-        let mut r = r.set("User-Agent", USER_AGENT);
+        let mut r = agent(self.redirects.try_into()?, offline())?.get(&self.url);
         for (k, v) in self.headers {
-            r = r.set(k.as_str(), v.as_str());
+            r = r.header(k.as_str(), v.as_str());
         }
-        Ok(r.call()?.into_string()?)
+        Ok(r.call()?.into_body().read_to_string()?)
     }
     pub fn head(&mut self, key: String, val: String) {
         self.headers.push((key, val));
@@ -239,3 +346,38 @@ impl Req {
         self.redirects = i;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn anitya() {
+        // trimmed response of /api/v2/versions/?project_id=7635 (fish)
+        let v: Value = serde_json::from_str(
+            r#"{
+                "latest_version": "4.0b1",
+                "stable_versions": ["3.7.1", "3.7.0", "3.6.4"],
+                "versions": ["4.0b1", "3.7.1", "3.7.0", "3.6.4"]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(anitya_version(&v), Some("3.7.1"));
+        let v: Value =
+            serde_json::from_str(r#"{"latest_version": "0.1rc1", "stable_versions": []}"#).unwrap();
+        assert_eq!(anitya_version(&v), Some("0.1rc1"));
+        assert_eq!(anitya_version(&Value::Null), None);
+    }
+
+    #[test]
+    fn offline_download() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+        // 192.0.2.0/24 is reserved for documentation; a real connection attempt would hang.
+        let err = download("http://192.0.2.1/", &dest, true).unwrap_err();
+        assert!(err.to_string().contains("ANDA_OFFLINE"), "{err}");
+        assert!(!dest.exists());
+        let err = agent(0, true).unwrap_err();
+        assert!(matches!(err, NetError::Offline), "{err}");
+    }
+}
@@ -8,7 +8,8 @@ mod error;
 mod fns;
 mod run;
 
-pub use fns::rpm::RPMSpec;
+pub use fns::rpm::{Changelog, Changelogs, RPMSpec, ValidationError};
+pub use fns::tsunagu::{download, get_req, offline, NetError};
 pub use rhai::{self, Map};
 pub use run::{errhdl, run};
 
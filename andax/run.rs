@@ -207,6 +207,9 @@ pub fn errhdl(name: &str, scr: &Path, err: EvalAltResult) {
 }
 
 /// Executes an AndaX script.
+///
+/// Every call creates a new [`Engine`] and [`Scope`], so variables, constants and functions
+/// defined by one script are never visible to another.
 pub fn run<
     'a,
     F: FnOnce(&mut Scope<'a>),
@@ -271,13 +274,10 @@ fn hint(sl: &str, lns: &str, nanitozo: &TbErr, rhai_fn: &str) -> Option<String>
                 return hint_ear(sl, lns, err, rhai_fn);
             }
             let s = format!("{err}");
-            if rhai_fn == "gh"
-                && s.starts_with("https://api.github.com/repos/")
-                && s.ends_with("/releases/latest: status code 404")
-            {
+            if rhai_fn == "gh" && s == "http status: 404" {
                 h!("Check if the repo is valid. Only releases are supported; use gh_tag() for tags.");
             }
-            if rhai_fn.starts_with("gh") && s.ends_with(": status code 403") {
+            if rhai_fn.starts_with("gh") && s == "http status: 403" {
                 h!("Maybe you have reached the ratelimit: https://docs.github.com/en/rest/using-the-rest-api/rate-limits-for-the-rest-api");
             }
             None
@@ -321,3 +321,29 @@ fn hint_ear(sl: &str, lns: &str, ear: &EvalAltResult, rhai_fn: &str) -> Option<S
     trace!("No hints");
     None
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn isolated_scope() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = (dir.path().join("first.rhai"), dir.path().join("second.rhai"));
+        std::fs::write(&first, "let leaked = 1; const LEAKED = 2; fn leaked() { 3 }").unwrap();
+        std::fs::write(
+            &second,
+            r#"if is_def_var("leaked") || is_def_var("LEAKED") || is_def_fn("leaked", 0) {
+                throw "state leaked from the previous script";
+            }
+            let ok = true;"#,
+        )
+        .unwrap();
+
+        let sc = run("first", &first, std::iter::empty::<(&str, &str)>(), |_| {}).unwrap();
+        assert!(sc.contains("leaked"));
+        let sc = run("second", &second, std::iter::empty::<(&str, &str)>(), |_| {})
+            .expect("second script saw state from the first one");
+        assert!(sc.get_value::<bool>("ok").unwrap());
+    }
+}
@@ -1,17 +1,298 @@
 use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
-use crate::cli::PackageType;
+use crate::{cli::PackageType, util::hash_file};
+use anda_config::Transform;
+use clap::ValueEnum;
+use color_eyre::{eyre::eyre, Result};
+use serde::{Deserialize, Serialize};
+
+/// File name of the artifact index in the output directory
+pub const MANIFEST_NAME: &str = ".anda-manifest.json";
+
+/// An entry in [`MANIFEST_NAME`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// File path, or image reference for OCI images
+    pub path: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    /// Only set for files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Only set for files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Digest reference of the base image, only set for OCI images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_digest: Option<String>,
+}
 
 #[derive(Clone)]
 pub struct Artifacts {
     pub packages: BTreeMap<String, PackageType>,
+    /// Base image digests of OCI images, by image reference
+    pub base_digests: BTreeMap<String, String>,
 }
 
 impl Artifacts {
     pub const fn new() -> Self {
-        Self { packages: BTreeMap::new() }
+        Self { packages: BTreeMap::new(), base_digests: BTreeMap::new() }
     }
     pub fn add(&mut self, name: String, package_type: PackageType) {
         self.packages.insert(name, package_type);
     }
+    pub fn set_base_digest(&mut self, name: String, digest: String) {
+        self.base_digests.insert(name, digest);
+    }
+    /// The architecture of an artifact, e.g. `noarch`, `x86_64` or `src`.
+    ///
+    /// Only RPMs have an architecture. It is taken from the file name of the built package
+    /// (`name-version-release.arch.rpm`), which is what `BuildArch:` resolves to in the end.
+    pub fn arch<'a>(&self, name: &'a str) -> Option<&'a str> {
+        if !matches!(self.packages.get(name)?, PackageType::Rpm) {
+            return None;
+        }
+        let (rest, _) = name.rsplit_once('/').map_or(name, |(_, f)| f).rsplit_once(".rpm")?;
+        rest.rsplit_once('.').map(|(_, arch)| arch).filter(|arch| !arch.is_empty())
+    }
+
+    /// Apply `transforms` in order to the artifacts that are files, changing them on disk.
+    ///
+    /// # Errors
+    /// - a pattern is not a valid regex
+    /// - a file would be replaced by a renamed or compressed file
+    /// - cannot rename or compress a file
+    pub fn transform(&mut self, transforms: &[Transform]) -> Result<()> {
+        for transform in transforms {
+            let (Transform::Rename { pattern, .. } | Transform::Gzip { pattern }) = transform;
+            let re = regex::Regex::new(pattern)
+                .map_err(|e| eyre!("Invalid transform pattern `{pattern}`: {e}"))?;
+            let matching: Vec<_> = self
+                .packages
+                .keys()
+                .map(PathBuf::from)
+                .filter(|p| p.is_file())
+                .filter(|p| p.file_name().is_some_and(|f| re.is_match(&f.to_string_lossy())))
+                .collect();
+            for path in matching {
+                let file = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                let dest = match transform {
+                    Transform::Rename { to, .. } => {
+                        path.with_file_name(re.replace(&file, to).as_ref())
+                    }
+                    Transform::Gzip { .. } => path.with_file_name(format!("{file}.gz")),
+                };
+                if dest == path {
+                    continue;
+                }
+                if dest.exists() {
+                    return Err(eyre!(
+                        "Cannot transform {}: {} exists",
+                        path.display(),
+                        dest.display()
+                    ));
+                }
+                match transform {
+                    Transform::Rename { .. } => std::fs::rename(&path, &dest)?,
+                    Transform::Gzip { .. } => {
+                        let mut gz = flate2::write::GzEncoder::new(
+                            std::fs::File::create(&dest)?,
+                            flate2::Compression::default(),
+                        );
+                        std::io::copy(&mut std::fs::File::open(&path)?, &mut gz)?;
+                        gz.finish()?;
+                        std::fs::remove_file(&path)?;
+                    }
+                }
+                if let Some(kind) = self.packages.remove(&path.display().to_string()) {
+                    self.packages.insert(dest.display().to_string(), kind);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Describe the artifacts, hashing the ones that are files.
+    ///
+    /// # Errors
+    /// - cannot read an artifact file
+    pub fn manifest_entries(&self) -> Result<Vec<ManifestEntry>> {
+        let mut entries = vec![];
+        for (name, kind) in &self.packages {
+            let path = Path::new(name);
+            let (size, sha256) = if path.is_file() {
+                (Some(path.metadata()?.len()), Some(hash_file::<sha2::Sha256>(path)?))
+            } else {
+                (None, None)
+            };
+            entries.push(ManifestEntry {
+                path: name.clone(),
+                kind: kind.to_possible_value().map(|v| v.get_name().to_owned()).unwrap_or_default(),
+                arch: self.arch(name).map(str::to_owned),
+                size,
+                sha256,
+                base_digest: self.base_digests.get(name).cloned(),
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Add the artifacts to the [`MANIFEST_NAME`] file in `dir`.
+    ///
+    /// Entries from earlier builds are kept unless they have the same path.
+    ///
+    /// # Errors
+    /// - cannot read an artifact file
+    /// - cannot read or write the manifest
+    pub fn write_manifest(&self, dir: &Path) -> Result<PathBuf> {
+        let manifest = dir.join(MANIFEST_NAME);
+        let mut entries: BTreeMap<String, ManifestEntry> = match std::fs::read(&manifest) {
+            Ok(data) => serde_json::from_slice::<Vec<ManifestEntry>>(&data)?
+                .into_iter()
+                .map(|e| (e.path.clone(), e))
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        entries.extend(self.manifest_entries()?.into_iter().map(|e| (e.path.clone(), e)));
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(
+            &manifest,
+            serde_json::to_vec_pretty(&entries.into_values().collect::<Vec<_>>())?,
+        )?;
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arch() {
+        let mut arts = Artifacts::new();
+        let noarch = "anda-build/rpm/rpms/foo-1.0-1.fc40.noarch.rpm";
+        let x86 = "anda-build/rpm/rpms/bar-1.0-1.fc40.x86_64.rpm";
+        let srpm = "anda-build/rpm/srpm/foo-1.0-1.fc40.src.rpm";
+        arts.add(noarch.to_owned(), PackageType::Rpm);
+        arts.add(x86.to_owned(), PackageType::Rpm);
+        arts.add(srpm.to_owned(), PackageType::Rpm);
+        arts.add("ghcr.io/fyralabs/foo:latest".to_owned(), PackageType::Docker);
+
+        assert_eq!(arts.arch(noarch), Some("noarch"));
+        assert_eq!(arts.arch(x86), Some("x86_64"));
+        assert_eq!(arts.arch(srpm), Some("src"));
+        assert_eq!(arts.arch("ghcr.io/fyralabs/foo:latest"), None);
+    }
+
+    #[test]
+    fn test_transform() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |f: &str| dir.path().join(f).display().to_string();
+        std::fs::write(path("foo-1.0-1.fc40.noarch.rpm"), b"foo").unwrap();
+        std::fs::write(path("foo-1.0-1.fc40.src.rpm"), b"src").unwrap();
+
+        let mut arts = Artifacts::new();
+        arts.add(path("foo-1.0-1.fc40.noarch.rpm"), PackageType::Rpm);
+        arts.add(path("foo-1.0-1.fc40.src.rpm"), PackageType::Rpm);
+        arts.add("ghcr.io/fyralabs/foo:1".to_owned(), PackageType::Docker);
+        arts.transform(&[
+            Transform::Rename {
+                pattern: r"^foo-(.+)\.noarch\.rpm$".to_owned(),
+                to: "bar-$1.noarch.rpm".to_owned(),
+            },
+            Transform::Gzip { pattern: r"\.src\.rpm$".to_owned() },
+        ])
+        .unwrap();
+
+        let names: Vec<_> = arts.packages.keys().cloned().collect();
+        assert_eq!(
+            names,
+            [
+                path("bar-1.0-1.fc40.noarch.rpm"),
+                path("foo-1.0-1.fc40.src.rpm.gz"),
+                "ghcr.io/fyralabs/foo:1".into()
+            ]
+        );
+        assert!(!Path::new(&path("foo-1.0-1.fc40.noarch.rpm")).exists());
+        assert!(!Path::new(&path("foo-1.0-1.fc40.src.rpm")).exists());
+        let mut src = String::new();
+        let mut gz = flate2::read::GzDecoder::new(
+            std::fs::File::open(path("foo-1.0-1.fc40.src.rpm.gz")).unwrap(),
+        );
+        std::io::Read::read_to_string(&mut gz, &mut src).unwrap();
+        assert_eq!(src, "src");
+
+        let err = arts.transform(&[Transform::Gzip { pattern: "(".to_owned() }]).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid transform pattern `(`"), "{err}");
+    }
+
+    #[test]
+    fn test_transform_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |f: &str| dir.path().join(f).display().to_string();
+        std::fs::write(path("foo-1.0-1.fc40.noarch.rpm"), b"foo").unwrap();
+        std::fs::write(path("bar-1.0-1.fc40.noarch.rpm"), b"bar").unwrap();
+
+        let mut arts = Artifacts::new();
+        arts.add(path("foo-1.0-1.fc40.noarch.rpm"), PackageType::Rpm);
+        let rename = Transform::Rename { pattern: "^foo-".to_owned(), to: "bar-".to_owned() };
+        let err = arts.transform(&[rename]).unwrap_err();
+        assert!(err.to_string().ends_with("bar-1.0-1.fc40.noarch.rpm exists"), "{err}");
+        assert_eq!(std::fs::read(path("bar-1.0-1.fc40.noarch.rpm")).unwrap(), b"bar");
+        assert!(Path::new(&path("foo-1.0-1.fc40.noarch.rpm")).exists());
+
+        // renaming a file to its own name does nothing
+        let rename = Transform::Rename { pattern: "^(foo-.+)$".to_owned(), to: "$1".to_owned() };
+        arts.transform(&[rename]).unwrap();
+        assert_eq!(arts.packages.keys().collect::<Vec<_>>(), [&path("foo-1.0-1.fc40.noarch.rpm")]);
+    }
+
+    #[test]
+    fn test_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let rpm = dir.path().join("foo-1.0-1.fc40.noarch.rpm").display().to_string();
+        std::fs::write(&rpm, b"foo").unwrap();
+
+        let mut arts = Artifacts::new();
+        arts.add(rpm.clone(), PackageType::Rpm);
+        arts.write_manifest(dir.path()).unwrap();
+
+        let mut arts = Artifacts::new();
+        arts.add("ghcr.io/fyralabs/foo:1".to_owned(), PackageType::Docker);
+        arts.set_base_digest("ghcr.io/fyralabs/foo:1".to_owned(), "fedora@sha256:abcd".to_owned());
+        let manifest = arts.write_manifest(dir.path()).unwrap();
+
+        // sorted by path
+        let entries: Vec<ManifestEntry> =
+            serde_json::from_slice(&std::fs::read(&manifest).unwrap()).unwrap();
+        assert_eq!(
+            entries,
+            [
+                ManifestEntry {
+                    path: rpm,
+                    kind: "rpm".to_owned(),
+                    arch: Some("noarch".to_owned()),
+                    size: Some(3),
+                    sha256: Some(
+                        "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"
+                            .to_owned()
+                    ),
+                    base_digest: None,
+                },
+                ManifestEntry {
+                    path: "ghcr.io/fyralabs/foo:1".to_owned(),
+                    kind: "docker".to_owned(),
+                    arch: None,
+                    size: None,
+                    sha256: None,
+                    base_digest: Some("fedora@sha256:abcd".to_owned()),
+                },
+            ]
+        );
+        assert!(std::fs::read_to_string(manifest).unwrap().contains(r#""base_digest": "fedora@"#));
+    }
 }
@@ -4,13 +4,18 @@ use crate::{
     cmd,
     flatpak::{FlatpakArtifact, FlatpakBuilder},
     oci::{build_oci, OCIBackend},
+    profile,
     rpm_spec::{RPMBuilder, RPMExtraOptions, RPMOptions},
+    sources::{parse_spec, stage_sources, verify_sources, SourceCache},
 };
 use anda_config::{Docker, Flatpak, Project};
 use color_eyre::{eyre::eyre, eyre::Context, Result};
 use itertools::Itertools;
-use std::path::{Path, PathBuf};
-use tracing::{debug, error, info, trace};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, error, info, trace, warn};
 
 pub async fn build_rpm(
     opts: &mut RPMOptions,
@@ -36,8 +41,6 @@ pub async fn build_rpm(
         debug!("No repodata found, skipping");
     }
 
-    opts.set_target(rpmb_opts.rpm_target.clone());
-
     for repo in &rpmb_opts.extra_repos {
         if opts.extra_repos.is_none() {
             opts.extra_repos = Some(vec![repo.clone()]);
@@ -46,14 +49,7 @@ pub async fn build_rpm(
         }
     }
 
-    for rpmmacro in &rpmb_opts.rpm_macro {
-        let split = rpmmacro.split_once(' ');
-        if let Some((key, value)) = split {
-            opts.def_macro(key, value);
-        } else {
-            return Err(eyre!("Invalid rpm macro: {rpmmacro}"));
-        }
-    }
+    apply_rpm_opts(opts, rpmb_opts)?;
     {
         // HACK: Define macro for autogitversion
         // get git version
@@ -76,15 +72,137 @@ pub async fn build_rpm(
         opts.def_macro("autogitdate", &date);
     };
 
-    trace!("Building RPMs with {opts:?}");
+    let parsed = parse_spec(spec, &opts.macros).await;
+    if let Some(e) = excluded_arch(spec, parsed.as_deref().ok(), opts) {
+        warn!("{}: {e}", spec.display());
+    }
 
-    let builder = builder.build(spec, opts).await?;
+    // stage the sources out of the working tree, rpm fetches them itself if the host cannot
+    // parse the spec, e.g. because rpm-build or the macro packages of the spec are missing
+    match parsed {
+        Ok(parsed) => {
+            let cache = SourceCache::new(SourceCache::default_dir(), rpmb_opts.refresh_sources);
+            let staging = output_dir.join("sources");
+            let stage = stage_sources(&cache, &parsed, &opts.sources, &staging);
+            profile::phase("source fetch", stage).await.wrap_err("Cannot prefetch sources")?;
+            opts.sources = staging;
+            if let Some(keyring) = &rpmb_opts.keyring {
+                let verify = verify_sources(&parsed, &opts.sources, keyring);
+                profile::phase("source verify", verify).await?;
+            }
+        }
+        Err(e) if rpmb_opts.keyring.is_some() => {
+            return Err(e.wrap_err("Cannot list the sources to verify"));
+        }
+        Err(e) => warn!("Not prefetching sources of {}: {e}", spec.display()),
+    }
 
-    cmd!(? "createrepo_c" "--quiet" "--update" {{repo_path.display()}})?;
+    trace!("Building RPMs with {opts:?}");
+
+    let builder = if rpmb_opts.srpm_only {
+        vec![profile::phase("srpm build", builder.build_srpm(spec, opts)).await?]
+    } else {
+        profile::phase("rpm build", builder.build(spec, opts)).await?
+    };
 
     Ok(builder)
 }
 
+/// The rpm name of the host architecture, e.g. `armv7hl` instead of `arm`.
+fn host_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86" => "i686",
+        "arm" => "armv7hl",
+        "powerpc64" if cfg!(target_endian = "little") => "ppc64le",
+        "powerpc64" => "ppc64",
+        arch => arch,
+    }
+}
+
+/// Check `ExclusiveArch:` and `ExcludeArch:` of the spec against the build target, or the host
+/// architecture if there is no target.
+///
+/// `parsed` is the spec expanded by [`parse_spec`]. Without it, architecture lists that use
+/// macros like `%{rust_arches}` cannot be checked.
+fn excluded_arch(
+    spec: &Path,
+    parsed: Option<&str>,
+    opts: &RPMOptions,
+) -> Option<andax::ValidationError> {
+    if !spec.is_file() {
+        return None;
+    }
+    // targets may be given as `arch-vendor-os`
+    let arch =
+        opts.target.as_deref().and_then(|t| t.split('-').next()).unwrap_or_else(|| host_arch());
+    let mut rpm = andax::RPMSpec::new(String::new(), "", spec);
+    if let Some(parsed) = parsed {
+        parsed.clone_into(&mut rpm.f);
+    }
+    rpm.check_arch(arch)
+}
+
+/// Check that `variant` is one of [`Project::variants`], i.e. sets every key of the matrix to one
+/// of its values.
+///
+/// # Errors
+/// - the variant is not in the project's matrix
+fn check_variant(proj: &Project, variant: &[(String, String)]) -> Result<()> {
+    let variant: BTreeMap<_, _> = variant.iter().cloned().collect();
+    let variants = proj.variants();
+    if variants.contains(&variant) {
+        return Ok(());
+    }
+    let fmt = |v: &BTreeMap<String, String>| v.iter().map(|(k, v)| format!("{k}={v}")).join(" ");
+    let valid = variants.iter().map(fmt).filter(|v| !v.is_empty()).join(", ");
+    if valid.is_empty() {
+        return Err(eyre!("Unknown variant `{}`, the project has no matrix", fmt(&variant)));
+    }
+    Err(eyre!("Unknown variant `{}`, valid variants: {valid}", fmt(&variant)))
+}
+
+/// Set the target and macros requested on the command line.
+///
+/// Macros from `--target` and `--nocheck` are applied first so that `-D` can override them.
+///
+/// # Errors
+/// - a macro passed via `-D` does not contain a value
+pub fn apply_rpm_opts(opts: &mut RPMOptions, rpmb_opts: &RpmOpts) -> Result<()> {
+    if rpmb_opts.offline && matches!(rpmb_opts.rpm_builder, crate::cli::RPMBuilder::Rpmbuild) {
+        // rpmbuild runs on the host, so there is nothing to cut off from the network
+        return Err(eyre!("--offline is not supported with --rpm-builder rpmbuild"));
+    }
+    opts.set_target(rpmb_opts.rpm_target.clone());
+    if let Some(target) = &rpmb_opts.dist_target {
+        opts.macros_iter(target.macros());
+        if opts.target.is_none() {
+            opts.set_target(target.arch.clone());
+        }
+        if opts.mock_config.is_none() {
+            opts.mock_config = Some(target.mock_config());
+        }
+    }
+    if rpmb_opts.nocheck {
+        // empties the `%check` section
+        opts.def_macro("__spec_check_body", "%{nil}");
+    }
+    define_macros(opts, &rpmb_opts.rpm_macro)
+}
+
+/// Define macros passed as `NAME VALUE` via `-D`/`--define`.
+///
+/// # Errors
+/// - a macro does not contain a value
+pub fn define_macros(opts: &mut RPMOptions, macros: &[String]) -> Result<()> {
+    for rpmmacro in macros {
+        let Some((key, value)) = rpmmacro.split_once(' ') else {
+            return Err(eyre!("Invalid rpm macro: {rpmmacro}"));
+        };
+        opts.def_macro(key, value);
+    }
+    Ok(())
+}
+
 /// Build a flatpak package.
 ///
 /// # Errors
@@ -112,11 +230,15 @@ pub async fn build_flatpak(
         builder.add_extra_source_url(std::mem::take(extra_source_url));
     }
 
+    if let Some(remote) = flatpak_opts.install_deps_from.take() {
+        builder.install_deps_from(remote);
+    }
+
     if !flatpak_opts.dont_delete_build_dir {
         builder.add_extra_args("--delete-build-dirs".to_owned());
     }
 
-    let flatpak = builder.build(manifest).await?;
+    let flatpak = profile::phase("flatpak build", builder.build(manifest)).await?;
     artifacts.push(FlatpakArtifact::Ref(flatpak.clone()));
     artifacts.push(FlatpakArtifact::Bundle(builder.bundle(&flatpak).await?));
 
@@ -221,31 +343,70 @@ pub async fn build_flatpak_call(
     Ok(())
 }
 
-pub fn build_oci_call(
+pub async fn build_oci_call(
     backend: OCIBackend,
     _cli: &Cli,
     manifest: &mut Docker,
     artifact_store: &mut Artifacts,
-) {
+    oci_opts: &OciOpts,
+) -> Result<()> {
     let art_type = match backend {
         OCIBackend::Docker => PackageType::Docker,
         OCIBackend::Podman => PackageType::Podman,
     };
 
     for (tag, image) in std::mem::take(&mut manifest.image) {
-        let art = build_oci(
-            backend,
-            &image.dockerfile.unwrap(),
-            image.tag_latest.unwrap_or(false),
-            &tag,
-            &image.version.unwrap_or_else(|| "latest".into()),
-            &image.context,
-        );
-
-        for artifact in art {
-            artifact_store.add(artifact.clone(), art_type);
+        let art = profile::phase(
+            "oci build",
+            build_oci(
+                backend,
+                &image.dockerfile.unwrap(),
+                image.tag_latest.unwrap_or(false),
+                &tag,
+                &image.version.unwrap_or_else(|| "latest".into()),
+                &image.context,
+                oci_opts,
+            ),
+        )
+        .await?;
+
+        for artifact in art.tags {
+            if let Some(digest) = &art.base_digest {
+                artifact_store.set_base_digest(artifact.clone(), digest.clone());
+            }
+            artifact_store.add(artifact, art_type);
         }
     }
+    Ok(())
+}
+
+/// Build the RPMs of an existing SRPM with the configured backend, for `--from-srpm`.
+///
+/// # Errors
+/// - the build failed
+/// - cannot update the repository or the artifact manifest
+pub async fn rebuild_srpm(cli: &Cli, rpmb_opts: &RpmOpts, srpm: &Path) -> Result<()> {
+    let cwd = std::env::current_dir()?;
+    let mut opts = RPMOptions::new(rpmb_opts.mock_config.clone(), cwd, cli.target_dir.clone());
+    opts.no_mirror = rpmb_opts.no_mirrors;
+    opts.offline = rpmb_opts.offline;
+    rpmb_opts.extra_repos.iter().for_each(|repo| opts.add_extra_repo(repo.clone()));
+    apply_rpm_opts(&mut opts, rpmb_opts)?;
+
+    let builder = RPMBuilder::from(rpmb_opts.rpm_builder);
+    let rpms = profile::phase("rpm build", builder.rebuild(srpm, &mut opts)).await?;
+    let repo_path = cli.target_dir.join("rpm");
+    cmd!(? "createrepo_c" "--quiet" "--update" {{repo_path.display()}})?;
+
+    let mut arts = Artifacts::new();
+    for rpm in rpms {
+        arts.add(rpm.to_string_lossy().to_string(), PackageType::Rpm);
+    }
+    for path in arts.packages.keys() {
+        println!("Built RPM ({}): {path}", arts.arch(path).unwrap_or("unknown"));
+    }
+    arts.write_manifest(&cli.target_dir)?;
+    Ok(())
 }
 
 // project parser
@@ -256,23 +417,35 @@ pub async fn build_project(
     package: PackageType,
     rbopts: &RpmOpts,
     fpopts: &FlatpakOpts,
-    _oci_opts: &OciOpts,
+    oci_opts: &OciOpts,
 ) -> Result<()> {
     let cwd = std::env::current_dir().unwrap();
 
     let mut rpm_opts = RPMOptions::new(rbopts.mock_config.clone(), cwd, cli.target_dir.clone());
 
-    // export environment variables
-    if let Some(env) = proj.env.as_ref() {
-        env.iter().for_each(|(k, v)| std::env::set_var(k, v));
+    if !rbopts.variant.is_empty() {
+        check_variant(&proj, &rbopts.variant)?;
+    }
+    // export environment variables, `--variant` values take precedence over the project's
+    proj.env.get_or_insert_with(Default::default).extend(rbopts.variant.iter().cloned());
+    for (k, v) in proj.env.iter().flatten() {
+        std::env::set_var(k, v);
+    }
+    for (k, v) in &proj.secrets {
+        std::env::set_var(k, v.expose());
     }
+    proj.check_env()?;
 
     if let Some(pre_script) = &proj.pre_script {
-        if pre_script.extension().unwrap_or_default() == "rhai" {
-            script!("pre_script", pre_script,);
-        } else {
-            cmd!(? "sh" "-c" {{ pre_script.display() }})?;
-        }
+        profile::phase("pre_script", async {
+            if pre_script.extension().unwrap_or_default() == "rhai" {
+                script!("pre_script", pre_script,);
+            } else {
+                cmd!(? "sh" "-c" {{ pre_script.display() }})?;
+            }
+            Ok::<_, color_eyre::Report>(())
+        })
+        .await?;
     }
 
     if let Some(rpmbuild) = &proj.rpm {
@@ -281,6 +454,7 @@ pub async fn build_project(
             rpm_opts.sources.clone_from(srcdir);
         }
         rpm_opts.no_mirror = rbopts.no_mirrors;
+        rpm_opts.offline = rbopts.offline;
         rpm_opts.def_macro("_disable_source_fetch", "0");
         rpm_opts.config_opts.push("external_buildrequires=True".to_owned());
 
@@ -309,9 +483,18 @@ pub async fn build_project(
     }
     let mut arts = Artifacts::new();
 
-    _build_pkg(package, &mut proj, cli, rpm_opts, rbopts, &mut arts, fpopts).await?;
+    _build_pkg(package, &mut proj, cli, rpm_opts, rbopts, &mut arts, fpopts, oci_opts).await?;
+    arts.transform(&proj.transforms)?;
+    if arts.packages.values().any(|kind| matches!(kind, PackageType::Rpm)) {
+        // after the transforms so that the metadata refers to the final files
+        let repo_path = cli.target_dir.join("rpm");
+        profile::phase("createrepo", async {
+            cmd!(? "createrepo_c" "--quiet" "--update" {{repo_path.display()}})
+        })
+        .await?;
+    }
 
-    for (path, arttype) in arts.packages {
+    for (path, arttype) in &arts.packages {
         let type_string = match arttype {
             PackageType::Rpm => "RPM",
             PackageType::Docker => "Docker image",
@@ -320,20 +503,32 @@ pub async fn build_project(
             // PackageType::RpmOstree => "rpm-ostree compose",
             PackageType::All => unreachable!(),
         };
-        println!("Built {type_string}: {path}");
+        if let Some(arch) = arts.arch(path) {
+            println!("Built {type_string} ({arch}): {path}");
+        } else {
+            println!("Built {type_string}: {path}");
+        }
     }
 
+    let manifest = arts.write_manifest(&cli.target_dir)?;
+    debug!("Wrote artifact manifest to {}", manifest.display());
+
     if let Some(post_script) = &proj.post_script {
-        if post_script.extension().unwrap_or_default() == "rhai" {
-            script!("post_script", post_script,);
-        } else {
-            cmd!(? "sh" "-c" {{ post_script.display() }})?;
-        }
+        profile::phase("post_script", async {
+            if post_script.extension().unwrap_or_default() == "rhai" {
+                script!("post_script", post_script,);
+            } else {
+                cmd!(? "sh" "-c" {{ post_script.display() }})?;
+            }
+            Ok::<_, color_eyre::Report>(())
+        })
+        .await?;
     }
 
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn _build_pkg(
     package: PackageType,
     proj: &mut Project,
@@ -342,9 +537,10 @@ async fn _build_pkg(
     rbopts: &RpmOpts,
     arts: &mut Artifacts,
     fpopts: &FlatpakOpts,
+    oci_opts: &OciOpts,
 ) -> Result<(), color_eyre::Report> {
     match package {
-        PackageType::All => build_all(proj, cli, rpm_opts, rbopts, arts, fpopts).await?,
+        PackageType::All => build_all(proj, cli, rpm_opts, rbopts, arts, fpopts, oci_opts).await?,
         PackageType::Rpm => {
             if let Some(rpmbuild) = &proj.rpm {
                 build_rpm_call(cli, rpm_opts, rpmbuild, rbopts.rpm_builder.into(), arts, rbopts)
@@ -355,16 +551,22 @@ async fn _build_pkg(
             }
         }
         PackageType::Docker => {
-            proj.docker.as_mut().map_or_else(
-                || println!("No Docker build defined for project"),
-                |docker| build_oci_call(OCIBackend::Docker, cli, docker, arts),
-            );
+            if let Some(docker) = proj.docker.as_mut() {
+                build_oci_call(OCIBackend::Docker, cli, docker, arts, oci_opts)
+                    .await
+                    .with_context(|| "Failed to build Docker images".to_owned())?;
+            } else {
+                println!("No Docker build defined for project");
+            }
         }
         PackageType::Podman => {
-            proj.podman.as_mut().map_or_else(
-                || println!("No Podman build defined for project"),
-                |podman| build_oci_call(OCIBackend::Podman, cli, podman, arts),
-            );
+            if let Some(podman) = proj.podman.as_mut() {
+                build_oci_call(OCIBackend::Podman, cli, podman, arts, oci_opts)
+                    .await
+                    .with_context(|| "Failed to build Podman images".to_owned())?;
+            } else {
+                println!("No Podman build defined for project");
+            }
         }
         PackageType::Flatpak => {
             if let Some(flatpak) = &proj.flatpak {
@@ -386,6 +588,7 @@ async fn build_all(
     rbopts: &RpmOpts,
     artifacts: &mut Artifacts,
     flatpak_opts: &FlatpakOpts,
+    oci_opts: &OciOpts,
 ) -> Result<(), color_eyre::Report> {
     if let Some(rpmbuild) = &project.rpm {
         build_rpm_call(cli, rpm_opts, rpmbuild, rbopts.rpm_builder.into(), artifacts, rbopts)
@@ -398,10 +601,14 @@ async fn build_all(
             .with_context(|| "Failed to build Flatpaks".to_owned())?;
     }
     if let Some(podman) = project.podman.as_mut() {
-        build_oci_call(OCIBackend::Podman, cli, podman, artifacts);
+        build_oci_call(OCIBackend::Podman, cli, podman, artifacts, oci_opts)
+            .await
+            .with_context(|| "Failed to build Podman images".to_owned())?;
     }
     if let Some(docker) = project.docker.as_mut() {
-        build_oci_call(OCIBackend::Docker, cli, docker, artifacts);
+        build_oci_call(OCIBackend::Docker, cli, docker, artifacts, oci_opts)
+            .await
+            .with_context(|| "Failed to build Docker images".to_owned())?;
     }
     if let Some(scripts) = &project.scripts {
         info!("Running build scripts");
@@ -411,16 +618,58 @@ async fn build_all(
                 .map(|p| p.to_string_lossy().to_string())
                 .collect::<Vec<String>>()
                 .as_slice(),
-            project.labels.iter().map(|(a, b)| (a.clone(), b.clone())).collect(),
+            &project.labels.iter().map(|(a, b)| (a.clone(), b.clone())).collect::<Vec<_>>(),
+            &[],
         )?;
     };
     Ok(())
 }
 
+/// Run `build` on each project in order.
+///
+/// Unless `keep_going` is set, this stops at the first failure. Otherwise the remaining
+/// projects are still built, and an error listing every failed project is returned at the end.
+///
+/// # Errors
+/// - any of the projects failed to build
+pub async fn build_each<I, F, Fut>(projects: I, keep_going: bool, mut build: F) -> Result<()>
+where
+    I: IntoIterator<Item = (String, Project)>,
+    F: FnMut(Project) -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    let (mut failed, mut succeeded) = (vec![], vec![]);
+    for (name, project) in projects {
+        println!("Building project: {name}");
+        profile::project(&name);
+        if let Err(e) = build(project).await {
+            if !keep_going {
+                return Err(e);
+            }
+            error!("Failed to build project {name}: {e:?}");
+            failed.push(name);
+        } else {
+            succeeded.push(name);
+        }
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(eyre!(
+            "{} project(s) failed to build: {} ({} succeeded)",
+            failed.len(),
+            failed.join(", "),
+            succeeded.len()
+        ))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn builder(
     cli: &Cli,
     rpm_opts: RpmOpts,
     all: bool,
+    keep_going: bool,
     project: Option<String>,
     package: PackageType,
     flatpak_opts: FlatpakOpts,
@@ -429,7 +678,7 @@ pub async fn builder(
     // Parse the project manifest
     // todo
     // ? can we assume cli.config won't be modified?
-    let config = anda_config::load_from_file(&cli.config.clone())?;
+    let mut config = anda_config::load_from_file(&cli.config.clone())?;
     trace!("all: {all}");
     trace!("project: {project:?}");
     trace!("package: {package:?}");
@@ -437,15 +686,22 @@ pub async fn builder(
     std::env::set_var("ANDA_TARGET_DIR", &cli.target_dir);
     std::env::set_var("ANDA_CONFIG_PATH", &cli.config);
 
+    if let Some(srpm) = &rpm_opts.from_srpm {
+        return rebuild_srpm(cli, &rpm_opts, srpm).await;
+    }
+
     if all {
-        for (name, project) in config.project {
-            println!("Building project: {name}");
-            build_project(cli, project, package, &rpm_opts, &flatpak_opts, &oci_opts).await?;
-        }
+        config.retain_enabled(Path::new("."))?;
+        let (rpm_opts, flatpak_opts, oci_opts) = (&rpm_opts, &flatpak_opts, &oci_opts);
+        build_each(config.project, keep_going, |project| {
+            build_project(cli, project, package, rpm_opts, flatpak_opts, oci_opts)
+        })
+        .await?;
     } else {
         // find project named project
         if let Some(name) = project {
             if let Some(project) = config.get_project(&name) {
+                profile::project(&name);
                 // cannot take: get_project() returns immut ref
                 build_project(cli, project.clone(), package, &rpm_opts, &flatpak_opts, &oci_opts)
                     .await?;
@@ -458,3 +714,198 @@ pub async fn builder(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{Command, DistTarget};
+    use crate::rpm_spec::MockBackend;
+    use clap::Parser;
+
+    #[tokio::test]
+    async fn test_keep_going() {
+        let projects = [("a", None), ("b", Some("b.spec"))].map(|(name, spec)| {
+            let rpm = spec.map(|s| anda_config::RpmBuild { spec: s.into(), ..Default::default() });
+            (name.to_owned(), Project { rpm, ..Default::default() })
+        });
+
+        let mut built = vec![];
+        let err = build_each(projects.clone(), true, |project| {
+            let res = project.rpm.map(|rpm| built.push(rpm.spec)).ok_or_else(|| eyre!("no rpm"));
+            std::future::ready(res)
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(built, [PathBuf::from("b.spec")]);
+        assert_eq!(err.to_string(), "1 project(s) failed to build: a (1 succeeded)");
+
+        let err = build_each(projects, false, |_| std::future::ready(Err(eyre!("no rpm"))))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "no rpm");
+    }
+
+    #[test]
+    fn test_define() {
+        let cli = Cli::try_parse_from(["anda", "build", "foo", "--define", "dist .fc40"]).unwrap();
+        let Command::Build { rpm_opts, .. } = cli.command else { panic!("not a build command") };
+        let mut opts = RPMOptions::new(None, PathBuf::new(), PathBuf::new());
+        define_macros(&mut opts, &rpm_opts.rpm_macro).unwrap();
+        assert_eq!(opts.macros.get("dist").map(String::as_str), Some(".fc40"));
+
+        let err = define_macros(&mut opts, &["dist".to_owned()]).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid rpm macro: dist");
+    }
+
+    #[test]
+    fn test_dist_target() {
+        let cli = Cli::try_parse_from(["anda", "build", "foo", "--target", "fedora-40"]).unwrap();
+        let Command::Build { rpm_opts, .. } = cli.command else { panic!("not a build command") };
+        let target = rpm_opts.dist_target.unwrap();
+        assert_eq!(target.arch, None);
+        let macros: BTreeMap<_, _> = target.macros().into_iter().collect();
+        assert_eq!(macros.get("dist").map(String::as_str), Some(".fc40"));
+        assert_eq!(macros.get("fedora").map(String::as_str), Some("40"));
+
+        let target: DistTarget = "centos-stream-9-aarch64".parse().unwrap();
+        assert_eq!(target.arch.as_deref(), Some("aarch64"));
+        let macros: BTreeMap<_, _> = target.macros().into_iter().collect();
+        assert_eq!(macros.get("dist").map(String::as_str), Some(".el9"));
+        assert_eq!(macros.get("rhel").map(String::as_str), Some("9"));
+        assert_eq!(macros.get("centos").map(String::as_str), Some("9"));
+
+        let cli =
+            Cli::try_parse_from(["anda", "build", "foo", "--target", "fedora-40-aarch64"]).unwrap();
+        let Command::Build { rpm_opts, .. } = cli.command else { panic!("not a build command") };
+        let mut opts =
+            RPMOptions::new(rpm_opts.mock_config.clone(), PathBuf::new(), PathBuf::new());
+        apply_rpm_opts(&mut opts, &rpm_opts).unwrap();
+        assert_eq!(opts.mock_config.as_deref(), Some("fedora-40-aarch64"));
+        assert_eq!(opts.target.as_deref(), Some("aarch64"));
+        let mut mock = MockBackend::new(opts.mock_config, PathBuf::new(), PathBuf::new());
+        mock.target(opts.target);
+        mock.macros_iter(opts.macros);
+        let args: Vec<_> =
+            mock.mock().as_std().get_args().map(|a| a.to_string_lossy().to_string()).collect();
+        assert_eq!(
+            args,
+            [
+                "-r",
+                "fedora-40-aarch64",
+                "--target",
+                "aarch64",
+                "-D",
+                "dist .fc40",
+                "-D",
+                "fc40 1",
+                "-D",
+                "fedora 40"
+            ]
+        );
+
+        // an explicit mock config is kept
+        let cli = Cli::try_parse_from([
+            "anda",
+            "build",
+            "foo",
+            "--target",
+            "fedora-40",
+            "-c",
+            "my-fedora",
+        ])
+        .unwrap();
+        let Command::Build { rpm_opts, .. } = cli.command else { panic!("not a build command") };
+        let mut opts =
+            RPMOptions::new(rpm_opts.mock_config.clone(), PathBuf::new(), PathBuf::new());
+        apply_rpm_opts(&mut opts, &rpm_opts).unwrap();
+        assert_eq!(opts.mock_config.as_deref(), Some("my-fedora"));
+
+        "debian-12".parse::<DistTarget>().unwrap_err();
+        "fedora-rawhide".parse::<DistTarget>().unwrap_err();
+    }
+
+    #[test]
+    fn test_from_srpm_args() {
+        let cli = Cli::try_parse_from(["anda", "build", "--from-srpm", "foo.src.rpm"]).unwrap();
+        let Command::Build { rpm_opts, .. } = cli.command else { panic!("not a build command") };
+        assert_eq!(rpm_opts.from_srpm, Some(PathBuf::from("foo.src.rpm")));
+
+        Cli::try_parse_from(["anda", "build", "foo", "--from-srpm", "foo.src.rpm"]).unwrap_err();
+    }
+
+    #[test]
+    fn test_excluded_arch() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = dir.path().join("foo.spec");
+        std::fs::write(&spec, "Name: foo\nExclusiveArch: x86_64 aarch64\n").unwrap();
+
+        let cli = Cli::try_parse_from(["anda", "build", "foo", "--rpm-target", "riscv64"]).unwrap();
+        let Command::Build { rpm_opts, .. } = cli.command else { panic!("not a build command") };
+        let mut opts = RPMOptions::new(None, PathBuf::new(), PathBuf::new());
+        apply_rpm_opts(&mut opts, &rpm_opts).unwrap();
+        assert_eq!(
+            excluded_arch(&spec, None, &opts),
+            Some(andax::ValidationError::ExcludedArch("riscv64".to_owned()))
+        );
+
+        opts.set_target(Some("aarch64-redhat-linux".to_owned()));
+        assert_eq!(excluded_arch(&spec, None, &opts), None);
+        assert_eq!(excluded_arch(&dir.path().join("missing.spec"), None, &opts), None);
+
+        // macros are only checked once expanded by rpmspec
+        std::fs::write(&spec, "Name: foo\nExclusiveArch: %{rust_arches}\n").unwrap();
+        opts.set_target(Some("riscv64".to_owned()));
+        assert_eq!(excluded_arch(&spec, None, &opts), None);
+        let parsed = "Name: foo\nExclusiveArch: x86_64 aarch64\n";
+        assert!(excluded_arch(&spec, Some(parsed), &opts).is_some());
+    }
+
+    #[test]
+    fn test_check_variant() {
+        let matrix = BTreeMap::from([
+            ("os".to_owned(), vec!["f40".to_owned()]),
+            ("python".to_owned(), vec!["3.11".to_owned(), "3.12".to_owned()]),
+        ]);
+        let proj = Project { matrix, ..Default::default() };
+        let variant = |v: &[(&str, &str)]| -> Vec<_> {
+            v.iter().map(|(k, v)| ((*k).to_owned(), (*v).to_owned())).collect()
+        };
+
+        check_variant(&proj, &variant(&[("python", "3.12"), ("os", "f40")])).unwrap();
+        let err = check_variant(&proj, &variant(&[("python", "3.12")])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown variant `python=3.12`, valid variants: os=f40 python=3.11, os=f40 python=3.12"
+        );
+        let err = check_variant(&Project::default(), &variant(&[("typo", "1")])).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown variant `typo=1`, the project has no matrix");
+    }
+
+    #[test]
+    fn test_nocheck() {
+        let cli = Cli::try_parse_from(["anda", "build", "foo", "--nocheck"]).unwrap();
+        let Command::Build { rpm_opts, .. } = cli.command else { panic!("not a build command") };
+        let mut opts = RPMOptions::new(None, PathBuf::new(), PathBuf::new());
+        apply_rpm_opts(&mut opts, &rpm_opts).unwrap();
+        assert_eq!(opts.macros.get("__spec_check_body").map(String::as_str), Some("%{nil}"));
+
+        let cli = Cli::try_parse_from(["anda", "build", "foo"]).unwrap();
+        let Command::Build { rpm_opts, .. } = cli.command else { panic!("not a build command") };
+        let mut opts = RPMOptions::new(None, PathBuf::new(), PathBuf::new());
+        apply_rpm_opts(&mut opts, &rpm_opts).unwrap();
+        assert!(!opts.macros.contains_key("__spec_check_body"));
+    }
+
+    #[test]
+    fn test_offline_rpmbuild() {
+        let cli =
+            Cli::try_parse_from(["anda", "build", "foo", "--rpm-builder", "rpmbuild"]).unwrap();
+        let Command::Build { rpm_opts, .. } = cli.command else { panic!("not a build command") };
+        let mut opts = RPMOptions::new(None, PathBuf::new(), PathBuf::new());
+        apply_rpm_opts(&mut opts, &rpm_opts).unwrap();
+
+        let rpm_opts = RpmOpts { offline: true, ..rpm_opts };
+        let err = apply_rpm_opts(&mut opts, &rpm_opts).unwrap_err();
+        assert_eq!(err.to_string(), "--offline is not supported with --rpm-builder rpmbuild");
+    }
+}
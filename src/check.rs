@@ -0,0 +1,58 @@
+//! Sanity checks for `anda check`
+use anda_config::Manifest;
+use andax::{RPMSpec, ValidationError};
+use color_eyre::{eyre::eyre, Result};
+
+/// The spec files of all RPM projects by project name.
+///
+/// # Errors
+/// - a spec file cannot be read
+pub fn specs(config: &Manifest) -> Result<Vec<(String, RPMSpec)>> {
+    let mut specs = vec![];
+    for (name, project) in &config.project {
+        let Some(rpm) = &project.rpm else { continue };
+        if !rpm.spec.is_file() {
+            return Err(eyre!("{name}: Cannot read spec file {}", rpm.spec.display()));
+        }
+        let update = project.update.clone().unwrap_or_default();
+        specs.push((name.clone(), RPMSpec::new(name.clone(), update, &rpm.spec)));
+    }
+    Ok(specs)
+}
+
+/// Validate the spec files of all RPM projects, see [`RPMSpec::validate`].
+///
+/// # Errors
+/// - a spec file cannot be read
+pub fn check_specs(config: &Manifest) -> Result<Vec<(String, ValidationError)>> {
+    Ok(specs(config)?
+        .into_iter()
+        .flat_map(|(name, spec)| spec.validate().into_iter().map(move |e| (name.clone(), e)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_specs() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = dir.path().join("foo.spec");
+        std::fs::write(
+            &spec,
+            "Name: foo\nVersion: 1\nRelease: 1\nSummary: Foo\n\n%description\nFoo.\n",
+        )
+        .unwrap();
+        let config = anda_config::load_from_string(&format!(
+            r#"project "foo" {{ rpm {{ spec = "{}" }} }}"#,
+            spec.display()
+        ))
+        .unwrap();
+        let problems = check_specs(&config).unwrap();
+        assert_eq!(problems, [("foo".to_owned(), ValidationError::MissingPreamble("License"))]);
+        let [(name, spec)] = &specs(&config).unwrap()[..] else { panic!("expected one spec") };
+        assert_eq!(name, "foo");
+        assert_eq!(spec.summary_report(), "foo: Foo\n");
+    }
+}
@@ -3,7 +3,7 @@
 
 use clap::{Args, Parser, Subcommand, ValueEnum};
 use clap_complete::Shell;
-use clap_verbosity_flag::InfoLevel;
+use clap_verbosity_flag::{log::LevelFilter, InfoLevel};
 use std::{path::PathBuf, str::FromStr};
 
 #[derive(ValueEnum, Debug, Clone, Copy, Default)]
@@ -39,6 +39,59 @@ impl FromStr for PackageType {
     }
 }
 
+/// A distribution to build RPMs for, in the same form as mock configs: `fedora-40`, `rhel-9`,
+/// `centos-stream-9`, optionally followed by an architecture, e.g. `fedora-40-aarch64`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DistTarget {
+    /// `fedora`, `rhel` or `centos-stream`
+    pub distro: String,
+    pub version: u32,
+    pub arch: Option<String>,
+}
+
+impl DistTarget {
+    /// The macros rpm defines on the distribution, e.g. `%dist .fc40` and `%fedora 40`.
+    #[must_use]
+    pub fn macros(&self) -> Vec<(String, String)> {
+        let v = self.version;
+        let (short, name) = if self.distro == "fedora" { ("fc", "fedora") } else { ("el", "rhel") };
+        let mut macros = vec![
+            ("dist".to_owned(), format!(".{short}{v}")),
+            (name.to_owned(), v.to_string()),
+            (format!("{short}{v}"), "1".to_owned()),
+        ];
+        if self.distro == "centos-stream" {
+            macros.push(("centos".to_owned(), v.to_string()));
+        }
+        macros
+    }
+
+    /// The name of the mock config for the distribution, e.g. `fedora-40-x86_64`.
+    ///
+    /// Without an architecture, the one of the host is used.
+    #[must_use]
+    pub fn mock_config(&self) -> String {
+        let arch = self.arch.as_deref().unwrap_or(std::env::consts::ARCH);
+        format!("{}-{}-{arch}", self.distro, self.version)
+    }
+}
+
+impl FromStr for DistTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (distro, rest) = ["fedora", "rhel", "centos-stream"]
+            .into_iter()
+            .find_map(|d| Some((d, s.strip_prefix(d)?.strip_prefix('-')?)))
+            .ok_or_else(|| {
+                format!("Invalid target: {s} (expected fedora-N, rhel-N or centos-stream-N)")
+            })?;
+        let (version, arch) = rest.split_once('-').map_or((rest, None), |(v, a)| (v, Some(a)));
+        let version = version.parse().map_err(|_| format!("Invalid version in target: {s}"))?;
+        Ok(Self { distro: distro.to_owned(), version, arch: arch.map(str::to_owned) })
+    }
+}
+
 /// Andaman is a package building toolchain that can automate building packages in various formats,
 /// such as RPM, Flatpak, Docker, etc.
 ///
@@ -59,14 +112,37 @@ pub struct Cli {
     pub config: PathBuf,
 
     #[clap(flatten)]
-    // #[clap(default_value = "info")]
-    pub verbose: clap_verbosity_flag::Verbosity<InfoLevel>,
+    pub verbose: Verbosity,
 
     /// Output directory for built packages
     #[clap(short, long, env = "TARGET_DIR", default_value = "anda-build")]
     pub target_dir: PathBuf,
 }
 
+/// Logging flags
+///
+/// Unlike `clap_verbosity_flag::Verbosity`, `-q` and `-v` can be used together,
+/// in which case `-q` wins.
+#[derive(Args, Debug, Clone, Copy, Default)]
+pub struct Verbosity {
+    /// Increase logging verbosity
+    #[clap(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Only show warnings, twice for errors only, thrice to disable logging
+    /// overrides `--verbose`
+    #[clap(short, long, action = clap::ArgAction::Count, global = true)]
+    quiet: u8,
+}
+
+impl Verbosity {
+    #[must_use]
+    pub fn log_level_filter(self) -> LevelFilter {
+        let verbose = if self.quiet == 0 { self.verbose } else { 0 };
+        clap_verbosity_flag::Verbosity::<InfoLevel>::new(verbose, self.quiet).log_level_filter()
+    }
+}
+
 #[derive(Args, Debug, Clone, Default)]
 pub struct FlatpakOpts {
     /// Flatpak: Extra source directory
@@ -82,6 +158,10 @@ pub struct FlatpakOpts {
     /// Flatpak: Do not delete the build directory
     #[clap(long, action)]
     pub dont_delete_build_dir: bool,
+
+    /// Flatpak: Install missing runtimes and SDKs from this remote
+    #[clap(long)]
+    pub install_deps_from: Option<String>,
 }
 
 #[derive(Args, Debug, Clone, Default)]
@@ -97,9 +177,19 @@ pub struct OciOpts {
     /// OCI: compress the context with gzip
     #[clap(long, action)]
     pub compress: bool,
+
+    /// OCI: Platforms to build for, e.g. `linux/amd64,linux/arm64`
+    /// can be defined multiple times, a manifest list is created for multiple platforms
+    #[clap(long)]
+    pub platform: Vec<String>,
+
+    /// Disable network access during the build, set by `anda build --offline`
+    #[clap(skip)]
+    pub offline: bool,
 }
 
 #[derive(Args, Debug, Clone, Default)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct RpmOpts {
     /// RPM: Do not mirror repositories.
     ///
@@ -117,9 +207,9 @@ pub struct RpmOpts {
     #[clap(long, short, value_enum, default_value = "mock")]
     pub rpm_builder: RPMBuilder,
 
-    /// RPM: Define a custom macro
+    /// RPM: Define a custom macro, e.g. `-D 'dist .fc40'`
     /// can be defined multiple times
-    #[clap(short = 'D', long)]
+    #[clap(short = 'D', long, visible_alias = "define")]
     pub rpm_macro: Vec<String>,
 
     /// RPM: A target to pass to rpmbuild/mock, useful for cross compilation
@@ -133,6 +223,45 @@ pub struct RpmOpts {
     /// RPM: Extra repositories to pass to mock
     #[clap(long, short = 'R')]
     pub extra_repos: Vec<String>,
+
+    /// RPM: Download remote sources again instead of using the source cache
+    #[clap(long, action)]
+    pub refresh_sources: bool,
+
+    /// RPM: Distribution to build for, e.g. `fedora-40` or `rhel-9-aarch64`
+    ///
+    /// This defines `%dist` and the version macros of the distribution, e.g. `%fedora`.
+    /// An architecture suffix is used as `--rpm-target` unless that is given.
+    /// Unless `--mock-config` is given, the matching mock config is used, e.g. `fedora-40-x86_64`.
+    /// Macros defined with `-D` take precedence.
+    #[clap(long = "target")]
+    pub dist_target: Option<DistTarget>,
+
+    /// RPM: Skip the `%check` section, like `rpmbuild --nocheck`
+    #[clap(long, action)]
+    pub nocheck: bool,
+
+    /// RPM: Rebuild an existing SRPM instead of building a project
+    #[clap(long, value_name = "SRPM", conflicts_with = "project")]
+    pub from_srpm: Option<PathBuf>,
+
+    /// RPM: Only build the SRPM, without building any RPMs
+    #[clap(long, action, conflicts_with = "from_srpm")]
+    pub srpm_only: bool,
+
+    /// RPM: Verify sources with a detached signature, e.g. `foo.tar.gz.asc`, against this keyring
+    ///
+    /// The build fails if a signature is bad.
+    #[clap(long, value_name = "KEYRING")]
+    pub keyring: Option<PathBuf>,
+
+    /// Disable network access when building RPMs from the SRPM, set by `anda build --offline`
+    #[clap(skip)]
+    pub offline: bool,
+
+    /// Matrix values to build with, set by `anda build --variant`
+    #[clap(skip)]
+    pub variant: Vec<(String, String)>,
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -148,6 +277,27 @@ pub enum Command {
         #[clap(short, long, action)]
         all: bool,
 
+        /// Continue building the other projects when one fails,
+        /// and report all failures at the end
+        #[clap(long, action, requires = "all")]
+        keep_going: bool,
+
+        /// Print how long each build phase took for each project
+        #[clap(long, action)]
+        profile: bool,
+
+        /// Disable network access in build containers for hermetic builds
+        ///
+        /// Sources are still fetched before the build. Cannot be used with `--rpm-builder rpmbuild`.
+        #[clap(long, action)]
+        offline: bool,
+
+        /// Values of the project's `matrix` to build with, e.g. `python=3.11`
+        ///
+        /// Each value is set as an environment variable and defined as an RPM macro.
+        #[clap(long)]
+        variant: Vec<String>,
+
         /// Project to build
         #[clap()]
         project: Option<String>,
@@ -169,11 +319,22 @@ pub enum Command {
         oci_opts: OciOpts,
     },
     /// Cleans up the build directory
-    Clean,
+    Clean {
+        /// Also remove the cache of downloaded RPM sources
+        #[clap(long, action)]
+        sources: bool,
+    },
 
     /// Lists all projects in the manifest
     List,
 
+    /// Checks the spec files of all RPM projects for missing preambles and other problems
+    Check {
+        /// Also print the packages of each spec with their summaries and requirements
+        #[clap(long, action)]
+        report: bool,
+    },
+
     /// Initializes a new project manifest
     Init {
         /// Path to the project manifest
@@ -222,6 +383,15 @@ pub enum Command {
         /// This will always override `--filters`.
         #[clap(short, long)]
         excludes: Vec<String>,
+        /// Commit each updated spec file
+        #[clap(long, action)]
+        commit: bool,
+        /// Commit message, `%{name}` and `%{version}` are replaced with the project and the new version
+        #[clap(long, default_value = "Update to %{version}")]
+        commit_message: String,
+        /// Push the commits to the upstream of the current branch, implies `--commit`
+        #[clap(long, action)]
+        push: bool,
     },
 
     /// Run .rhai scripts
@@ -229,5 +399,11 @@ pub enum Command {
         scripts: Vec<String>,
         #[clap(short, long)]
         labels: Option<String>,
+        /// Run the scripts again each time one of them changes
+        #[clap(short, long, action)]
+        watch: bool,
+        /// Arguments passed to the scripts, available as the `args` array
+        #[clap(last = true)]
+        args: Vec<String>,
     },
 }
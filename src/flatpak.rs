@@ -38,6 +38,8 @@ pub struct FlatpakBuilder {
     extra_sources_urls: Vec<String>,
     // extra arguments to pass to flatpak-builder
     extra_args: Vec<String>,
+    // Remote to install missing dependencies from
+    install_deps_from: Option<String>,
 }
 
 impl FlatpakBuilder {
@@ -49,6 +51,7 @@ impl FlatpakBuilder {
             extra_sources: Vec::new(),
             extra_sources_urls: Vec::new(),
             extra_args: Vec::new(),
+            install_deps_from: None,
         }
     }
 
@@ -77,25 +80,22 @@ impl FlatpakBuilder {
         self.extra_sources_urls.extend(iter);
     }
 
-    pub async fn build(&self, manifest: &Path) -> Result<String> {
-        // we parse the flatpak metadata file
-        let flatpak_meta = FlatpakApplication::load_from_file(manifest.display().to_string())
-            .map_err(color_eyre::Report::msg)?;
-
-        // create the flatpak output folders
-        let output_dir =
-            env::current_dir()?.join(".flatpak-builder/build").join(&flatpak_meta.app_id);
-        std::fs::create_dir_all(&output_dir)?;
-        std::fs::create_dir_all(&self.output_repo)?;
+    pub fn install_deps_from(&mut self, remote: String) {
+        self.install_deps_from = Some(remote);
+    }
 
-        // build the flatpak
+    /// Assemble the flatpak-builder invocation.
+    ///
+    /// # Errors
+    /// - cannot canonicalize the output repository path
+    pub fn command(&self, output_dir: &Path, manifest: &Path) -> Result<Command> {
         let mut flatpak = Command::new("flatpak-builder");
         flatpak
             .arg(output_dir)
             .arg(manifest)
             .arg("--force-clean")
             .arg("--repo")
-            .arg(self.output_repo.canonicalize().unwrap());
+            .arg(self.output_repo.canonicalize()?);
 
         // add extra sources
 
@@ -107,7 +107,29 @@ impl FlatpakBuilder {
             flatpak.arg("--extra-sources-url").arg(source);
         }
 
+        if let Some(remote) = &self.install_deps_from {
+            // dependencies with extra-data (e.g. codecs) ask for confirmation before
+            // downloading, so the install has to be non-interactive
+            flatpak.arg("--install-deps-from").arg(remote).arg("--assumeyes");
+        }
+
         flatpak.args(&self.extra_args);
+        Ok(flatpak)
+    }
+
+    pub async fn build(&self, manifest: &Path) -> Result<String> {
+        // we parse the flatpak metadata file
+        let flatpak_meta = FlatpakApplication::load_from_file(manifest.display().to_string())
+            .map_err(color_eyre::Report::msg)?;
+
+        // create the flatpak output folders
+        let output_dir =
+            env::current_dir()?.join(".flatpak-builder/build").join(&flatpak_meta.app_id);
+        std::fs::create_dir_all(&output_dir)?;
+        std::fs::create_dir_all(&self.output_repo)?;
+
+        // build the flatpak
+        let mut flatpak = self.command(&output_dir, manifest)?;
 
         // run the command
         flatpak.log().await?;
@@ -133,4 +155,22 @@ impl FlatpakBuilder {
 }
 
 #[cfg(test)]
-mod test_super {}
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_install_deps_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut builder = FlatpakBuilder::new(
+            dir.path().join("build"),
+            dir.path().to_path_buf(),
+            dir.path().join("bundles"),
+        );
+        builder.install_deps_from("internal".to_owned());
+
+        let cmd = builder.command(Path::new("build"), Path::new("app.yml")).unwrap();
+        let args: Vec<_> = cmd.as_std().get_args().map(|a| a.to_string_lossy()).collect();
+        assert!(args.windows(2).any(|w| w == ["--install-deps-from", "internal"]));
+        assert!(args.iter().any(|a| a == "--assumeyes"));
+    }
+}
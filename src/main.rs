@@ -4,10 +4,13 @@
 
 mod artifacts;
 mod builder;
+mod check;
 mod cli;
 mod flatpak;
 mod oci;
+mod profile;
 mod rpm_spec;
+mod sources;
 mod update;
 mod util;
 use anda_config::parse_labels;
@@ -15,12 +18,13 @@ use clap::{CommandFactory, Parser};
 use clap_complete::generate;
 use cli::{Cli, Command};
 use color_eyre::{eyre::eyre, Result};
-use std::{io, mem::take};
+use std::{io, mem::take, path::Path};
 use tracing::{debug, trace};
 
 #[allow(clippy::unwrap_in_result)]
 #[allow(clippy::missing_errors_doc)]
 #[allow(clippy::missing_panics_doc)]
+#[allow(clippy::too_many_lines)]
 #[tokio::main]
 async fn main() -> Result<()> {
     color_eyre::install()?;
@@ -39,13 +43,17 @@ async fn main() -> Result<()> {
     match cli.command {
         Command::Build {
             all,
+            keep_going,
+            profile,
+            offline,
+            ref variant,
             ref mut project,
             ref mut package,
             ref mut rpm_opts,
             ref mut flatpak_opts,
             ref mut oci_opts,
         } => {
-            if project.is_none() && !all {
+            if project.is_none() && !all && rpm_opts.from_srpm.is_none() {
                 // print help
                 let mut app = Cli::command();
                 let a = app.find_subcommand_mut("build").unwrap();
@@ -57,12 +65,33 @@ async fn main() -> Result<()> {
             let project = take(project);
             let package = std::mem::replace(package, cli::PackageType::Rpm);
             let flatpak_opts = take(flatpak_opts);
-            let oci_opts = take(oci_opts);
-            let rpm_opts = take(rpm_opts);
+            let oci_opts = cli::OciOpts { offline, ..take(oci_opts) };
+            let mut rpm_opts = cli::RpmOpts { offline, ..take(rpm_opts) };
+            let variant = parse_labels(variant.iter().map(std::ops::Deref::deref))
+                .ok_or_else(|| eyre!("Cannot parse --variant"))?;
+            rpm_opts.rpm_macro.extend(variant.iter().map(|(key, value)| format!("{key} {value}")));
+            rpm_opts.variant = variant;
             debug!("{all:?}");
-            builder::builder(&cli, rpm_opts, all, project, package, flatpak_opts, oci_opts).await?;
+            if profile {
+                profile::enable();
+            }
+            let res = builder::builder(
+                &cli,
+                rpm_opts,
+                all,
+                keep_going,
+                project,
+                package,
+                flatpak_opts,
+                oci_opts,
+            )
+            .await;
+            if let Some(report) = profile::report() {
+                print!("{report}");
+            }
+            res?;
         }
-        Command::Clean => {
+        Command::Clean { sources } => {
             println!("Cleaning up build directory");
             let clean = std::fs::remove_dir_all(&cli.target_dir);
             if let Err(e) = clean {
@@ -72,6 +101,11 @@ async fn main() -> Result<()> {
                     e => println!("Error cleaning up build directory: {e:?}"),
                 }
             }
+            if sources {
+                let dir = sources::SourceCache::default_dir();
+                let reclaimed = sources::prune_cache(&dir)?;
+                println!("Removed source cache {} ({reclaimed} bytes reclaimed)", dir.display());
+            }
         }
 
         Command::List => {
@@ -88,6 +122,21 @@ async fn main() -> Result<()> {
 
             trace!("{config:#?}");
         }
+        Command::Check { report } => {
+            let config = anda_config::load_from_file(&cli.config)?;
+            if report {
+                for (name, spec) in check::specs(&config)? {
+                    print!("[{name}]\n{}", spec.summary_report());
+                }
+            }
+            let problems = check::check_specs(&config)?;
+            for (name, problem) in &problems {
+                println!("{name}: {problem}");
+            }
+            if !problems.is_empty() {
+                return Err(eyre!("Found {} problems in spec files", problems.len()));
+            }
+        }
         Command::Init { path, yes } => {
             // create a new project
             debug!("Creating new project in {}", path.display());
@@ -97,27 +146,43 @@ async fn main() -> Result<()> {
             generate(shell, &mut cli::Cli::command(), "anda", &mut io::stdout());
         }
         Command::CI => {
-            let config = anda_config::load_from_file(&cli.config)?;
+            let mut config = anda_config::load_from_file(&cli.config)?;
+            config.retain_enabled(Path::new("."))?;
             let entries = util::fetch_build_entries(config);
 
             println!("build_matrix={}", serde_json::to_string(&entries)?);
         }
-        Command::Update { labels, filters, excludes } => {
+        Command::Update { labels, filters, excludes, commit, commit_message, push } => {
             let labels = parse_labels(labels.iter().map(std::ops::Deref::deref))
                 .ok_or_else(|| eyre!("Cannot parse --labels"))?;
             let filters = anda_config::parse_filters(&filters)
                 .ok_or_else(|| eyre!("Cannot parse --filters"))?;
             let excludes = anda_config::parse_filters(&excludes)
                 .ok_or_else(|| eyre!("Cannot parse --excludes"))?;
-            update::update(anda_config::load_from_file(&cli.config)?, labels, filters, excludes)?;
+            let commit =
+                (commit || push).then_some(update::CommitOpts { message: commit_message, push });
+            update::update(
+                anda_config::load_from_file(&cli.config)?,
+                labels,
+                filters,
+                excludes,
+                commit.as_ref(),
+            )?;
         }
-        Command::Run { scripts, labels } => {
+        Command::Run { scripts, labels, watch, args } => {
             if scripts.is_empty() {
                 return Err(eyre!("No scripts to run"));
             }
             let labels = parse_labels(labels.iter().map(std::ops::Deref::deref))
                 .ok_or_else(|| eyre!("Cannot parse --labels"))?;
-            update::run_scripts(&scripts, labels)?;
+            if watch {
+                update::watch(&scripts, std::time::Duration::from_millis(500), || {
+                    update::run_scripts(&scripts, &labels, &args)?;
+                    Ok(std::ops::ControlFlow::Continue(()))
+                })?;
+            } else {
+                update::run_scripts(&scripts, &labels, &args)?;
+            }
         }
     }
     Ok(())
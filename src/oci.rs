@@ -1,6 +1,9 @@
 //! OCI Builder backend
 //! Supports Docker and Podman
-use std::process::Command;
+use crate::util::CommandLog;
+use color_eyre::{eyre::eyre, Result};
+use std::{io::Write, path::Path, process::Command};
+use tracing::{info, warn};
 
 #[derive(Clone, Copy)]
 pub enum OCIBackend {
@@ -19,63 +22,351 @@ impl OCIBackend {
     }
 }
 
+/// The image the final stage of a Dockerfile is based on.
+///
+/// Stages based on earlier stages are followed back to their image. Returns `None` for
+/// `scratch` and for images that depend on build arguments.
+pub fn base_image(dockerfile: &str) -> Option<String> {
+    let mut stages: Vec<(String, Option<String>)> = vec![];
+    for line in dockerfile.lines() {
+        let mut words = line.split_whitespace();
+        if !words.next().is_some_and(|w| w.eq_ignore_ascii_case("FROM")) {
+            continue;
+        }
+        let mut words = words.skip_while(|w| w.starts_with("--"));
+        let Some(image) = words.next() else { continue };
+        let image = stages
+            .iter()
+            .rev()
+            .find(|(_, name)| name.as_deref().is_some_and(|n| n.eq_ignore_ascii_case(image)))
+            .map_or_else(|| image.to_owned(), |(base, _)| base.clone());
+        let name = words.next().filter(|w| w.eq_ignore_ascii_case("AS")).and_then(|_| words.next());
+        stages.push((image, name.map(str::to_owned)));
+    }
+    let (image, _) = stages.pop()?;
+    (image != "scratch" && !image.contains('$')).then_some(image)
+}
+
+/// Replace `image` in the `FROM` instructions of a Dockerfile with `pinned`, usually the digest
+/// reference from [`OCIBuilder::resolve_digest`].
+pub fn pin_base_image(dockerfile: &str, image: &str, pinned: &str) -> String {
+    let pin = |line: &str| {
+        let mut words = line.split_whitespace();
+        if !words.next().is_some_and(|w| w.eq_ignore_ascii_case("FROM")) {
+            return None;
+        }
+        // the flags before the image cannot contain the image itself
+        words.find(|w| !w.starts_with("--")).filter(|w| *w == image)?;
+        Some(line.replacen(image, pinned, 1))
+    };
+    dockerfile
+        .split_inclusive('\n')
+        .map(|line| pin(line).unwrap_or_else(|| line.to_owned()))
+        .collect()
+}
+
 pub struct OCIBuilder {
     context: String,
     tag: String,
     version: String,
     label: Vec<String>,
+    /// Platforms to build for, e.g. `linux/amd64`.
+    /// If there is more than one, a manifest list is created.
+    platforms: Vec<String>,
+    /// Build without network access
+    offline: bool,
 }
 
 impl OCIBuilder {
     pub const fn new(context: String, tag: String, version: String) -> Self {
-        Self { context, tag, version, label: Vec::new() }
+        Self { context, tag, version, label: Vec::new(), platforms: Vec::new(), offline: false }
     }
 
     pub fn add_label(&mut self, label: String) {
         self.label.push(label);
     }
 
+    pub const fn offline(&mut self, offline: bool) {
+        self.offline = offline;
+    }
+
+    pub fn platforms_iter<I: IntoIterator<Item = String>>(&mut self, iter: I) {
+        self.platforms.extend(iter);
+    }
+
     // We use string here because we want to let people use stuff like git contexts
-    pub fn build(&self, dockerfile: &str, backend: OCIBackend, latest: bool) {
+    pub fn command(&self, dockerfile: &str, backend: OCIBackend, latest: bool) -> Command {
         let mut cmd = backend.command();
 
         let real_tag = &format!("{}:{}", &self.tag, self.version);
 
-        cmd.arg("build")
-            .arg(&self.context)
-            .arg("-f")
-            .arg(dockerfile)
-            .arg("-t")
-            .env("DOCKER_BUILDKIT", "1")
-            .arg(real_tag);
+        cmd.arg("build").arg(&self.context).arg("-f").arg(dockerfile).env("DOCKER_BUILDKIT", "1");
+
+        if !self.platforms.is_empty() {
+            cmd.arg("--platform").arg(self.platforms.join(","));
+        }
+
+        // podman needs `--manifest` to put images of different platforms into a manifest list,
+        // docker (buildx) creates the list by itself. `-t` would tag the last built image with a
+        // reference, so `latest` is tagged after the build instead, see `build()`.
+        if self.manifest_list(backend) {
+            cmd.arg("--manifest").arg(real_tag);
+        } else {
+            cmd.arg("-t").arg(real_tag);
+            if latest {
+                cmd.arg("-t").arg(format!("{}:latest", &self.tag));
+            }
+        }
 
-        if latest {
-            cmd.arg("-t").arg(format!("{}:latest", &self.tag));
+        if self.offline {
+            cmd.arg("--network=none");
         }
 
         for label in &self.label {
             cmd.arg("--label").arg(label);
         }
+
+        cmd
+    }
+
+    /// Whether the images are collected into a manifest list with `--manifest`
+    const fn manifest_list(&self, backend: OCIBackend) -> bool {
+        self.platforms.len() > 1 && matches!(backend, OCIBackend::Podman)
+    }
+
+    /// Get the digest reference of `image`, e.g. `registry.fedoraproject.org/fedora@sha256:…`
+    ///
+    /// The image is pulled first if `pull` is set, otherwise only the local copy is inspected.
+    ///
+    /// # Errors
+    /// - cannot pull or inspect the image
+    pub async fn resolve_digest(backend: OCIBackend, image: &str, pull: bool) -> Result<String> {
+        if pull {
+            let mut pull = tokio::process::Command::from(backend.command());
+            pull.arg("pull").arg(image).log().await?;
+        }
+        let mut inspect = backend.command();
+        inspect.args(["image", "inspect", "--format", "{{index .RepoDigests 0}}", image]);
+        let out = tokio::process::Command::from(inspect).output().await?;
+        if !out.status.success() {
+            return Err(eyre!("Cannot inspect {image}: {}", String::from_utf8_lossy(&out.stderr)));
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).trim().to_owned())
+    }
+
+    /// Build the image.
+    ///
+    /// # Errors
+    /// - the build command failed
+    pub async fn build(&self, dockerfile: &str, backend: OCIBackend, latest: bool) -> Result<()> {
+        let cmd = self.command(dockerfile, backend, latest);
+        tokio::process::Command::from(cmd).log().await?;
+        if latest && self.manifest_list(backend) {
+            let mut tag = tokio::process::Command::from(backend.command());
+            tag.arg("tag").arg(format!("{}:{}", self.tag, self.version));
+            tag.arg(format!("{}:latest", self.tag)).log().await?;
+        }
+        info!("Built {}:{}", self.tag, self.version);
+        Ok(())
+    }
+}
+
+/// References of a built image, `{tag}:{version}` and `{tag}:latest` if `latest` is set.
+fn image_tags(tag: &str, version: &str, latest: bool) -> Vec<String> {
+    let mut tags = vec![format!("{tag}:{version}")];
+    if latest {
+        tags.push(format!("{tag}:latest"));
     }
+    tags
+}
+
+/// A built image
+pub struct OCIArtifact {
+    /// Image references, see [`image_tags`]
+    pub tags: Vec<String>,
+    /// Digest reference the base image was pinned to for the build
+    pub base_digest: Option<String>,
+}
+
+/// Write a copy of the Dockerfile with the base image pinned to `digest`.
+///
+/// The copy is placed next to the original so that relative paths in it resolve the same way; it is
+/// removed when the returned file is dropped.
+fn pinned_dockerfile(
+    dockerfile: &str,
+    content: &str,
+    image: &str,
+    digest: &str,
+) -> Result<tempfile::NamedTempFile> {
+    let dir = Path::new(dockerfile).parent().filter(|p| !p.as_os_str().is_empty());
+    let mut file = tempfile::Builder::new()
+        .prefix(".anda-pinned-")
+        .suffix(".Dockerfile")
+        .tempfile_in(dir.unwrap_or_else(|| Path::new(".")))?;
+    file.write_all(pin_base_image(content, image, digest).as_bytes())?;
+    Ok(file)
 }
 
-pub fn build_oci(
+pub async fn build_oci(
     backend: OCIBackend,
     dockerfile: &str,
     latest: bool,
     tag: &str,
     version: &str,
     context: &str,
-) -> Vec<String> {
+    oci_opts: &crate::cli::OciOpts,
+) -> Result<OCIArtifact> {
     let mut builder = OCIBuilder::new(context.to_owned(), tag.to_owned(), version.to_owned());
     builder.add_label(format!("com.fyralabs.anda.version={}", env!("CARGO_PKG_VERSION")));
+    builder.platforms_iter(oci_opts.platform.iter().flat_map(|p| p.split(',')).map(str::to_owned));
+    builder.offline(oci_opts.offline);
 
-    builder.build(dockerfile, backend, latest);
+    // pin the base image to its current digest so that the build uses the recorded digest,
+    // offline builds can only use an image that has already been pulled
+    let source = std::fs::read_to_string(dockerfile).ok();
+    let mut pinned = None;
+    let base_digest = match source.as_deref().and_then(base_image) {
+        Some(image) => match OCIBuilder::resolve_digest(backend, &image, !oci_opts.offline).await {
+            Ok(digest) => {
+                info!("Base image {image} resolved to {digest}");
+                pinned = Some(pinned_dockerfile(
+                    dockerfile,
+                    source.as_deref().unwrap_or_default(),
+                    &image,
+                    &digest,
+                )?);
+                Some(digest)
+            }
+            Err(e) => {
+                warn!("Cannot resolve digest of base image {image}: {e}");
+                None
+            }
+        },
+        None => None,
+    };
 
-    let mut tags = vec![format!("{tag}:{version}")];
+    let pinned_path = pinned.as_ref().map(|f| f.path().to_string_lossy().into_owned());
+    builder.build(pinned_path.as_deref().unwrap_or(dockerfile), backend, latest).await?;
 
-    if latest {
-        tags.push(format!("{tag}:latest"));
+    Ok(OCIArtifact { tags: image_tags(tag, version, latest), base_digest })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base_image() {
+        let dockerfile = "\
+FROM --platform=$BUILDPLATFORM registry.fedoraproject.org/fedora:40 AS build
+RUN make
+
+FROM build as final
+COPY --from=build /out /out
+";
+        assert_eq!(base_image(dockerfile).as_deref(), Some("registry.fedoraproject.org/fedora:40"));
+        assert_eq!(base_image("FROM fedora:latest\nFROM scratch\n"), None);
+        assert_eq!(base_image("ARG BASE\nFROM $BASE\n"), None);
+        assert_eq!(base_image("RUN true\n"), None);
+    }
+
+    #[test]
+    fn test_pin_base_image() {
+        let dockerfile = "\
+FROM --platform=$BUILDPLATFORM fedora:40 AS build
+RUN echo fedora:40
+
+from fedora:40
+COPY --from=build /out /out
+";
+        assert_eq!(
+            pin_base_image(dockerfile, "fedora:40", "fedora@sha256:abc"),
+            "\
+FROM --platform=$BUILDPLATFORM fedora@sha256:abc AS build
+RUN echo fedora:40
+
+from fedora@sha256:abc
+COPY --from=build /out /out
+"
+        );
+        assert_eq!(pin_base_image("FROM fedora:400\n", "fedora:40", "x"), "FROM fedora:400\n");
+    }
+
+    #[test]
+    fn test_multiarch_command() {
+        let mut builder = OCIBuilder::new(".".into(), "ghcr.io/fyralabs/foo".into(), "1".into());
+        builder.platforms_iter(["linux/amd64".to_owned(), "linux/arm64".to_owned()]);
+
+        // `latest` is tagged separately so that it does not collide with the manifest list
+        let cmd = builder.command("Dockerfile", OCIBackend::Podman, true);
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
+        assert_eq!(
+            args,
+            [
+                "build",
+                ".",
+                "-f",
+                "Dockerfile",
+                "--platform",
+                "linux/amd64,linux/arm64",
+                "--manifest",
+                "ghcr.io/fyralabs/foo:1"
+            ]
+        );
+
+        let cmd = builder.command("Dockerfile", OCIBackend::Docker, true);
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
+        assert_eq!(
+            args,
+            [
+                "build",
+                ".",
+                "-f",
+                "Dockerfile",
+                "--platform",
+                "linux/amd64,linux/arm64",
+                "-t",
+                "ghcr.io/fyralabs/foo:1",
+                "-t",
+                "ghcr.io/fyralabs/foo:latest"
+            ]
+        );
+
+        // a single platform does not need a manifest list
+        let mut builder = OCIBuilder::new(".".into(), "foo".into(), "1".into());
+        builder.platforms_iter(["linux/arm64".to_owned()]);
+        builder.add_label("a=b".to_owned());
+        let cmd = builder.command("Dockerfile", OCIBackend::Podman, false);
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
+        assert_eq!(
+            args,
+            [
+                "build",
+                ".",
+                "-f",
+                "Dockerfile",
+                "--platform",
+                "linux/arm64",
+                "-t",
+                "foo:1",
+                "--label",
+                "a=b"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_image_tags() {
+        assert_eq!(image_tags("ghcr.io/fyralabs/foo", "1", false), ["ghcr.io/fyralabs/foo:1"]);
+        assert_eq!(image_tags("foo", "1", true), ["foo:1", "foo:latest"]);
+    }
+
+    #[test]
+    fn test_offline_command() {
+        let mut builder = OCIBuilder::new(".".into(), "foo".into(), "1".into());
+        builder.offline(true);
+        let cmd = builder.command("Dockerfile", OCIBackend::Podman, false);
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
+        assert_eq!(args, ["build", ".", "-f", "Dockerfile", "-t", "foo:1", "--network=none"]);
     }
-    tags
 }
@@ -0,0 +1,79 @@
+//! Timing of build phases for `anda build --profile`
+//!
+//! Profiling is off unless [`enable`] is called, in which case every [`phase`] is recorded
+//! under the project most recently passed to [`project`].
+
+use parking_lot::Mutex;
+use std::fmt::Write;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Durations of each phase, grouped by project in build order
+type Projects = Vec<(String, Vec<(&'static str, Duration)>)>;
+
+static PROFILE: Mutex<Option<Projects>> = Mutex::new(None);
+
+fn with<T, F: FnOnce(&mut Option<Projects>) -> T>(f: F) -> T {
+    f(&mut PROFILE.lock())
+}
+
+pub fn enable() {
+    with(|p| *p = Some(vec![]));
+}
+
+/// Record the following phases under `name`.
+pub fn project(name: &str) {
+    with(|p| p.as_mut().map(|p| p.push((name.to_owned(), vec![]))));
+}
+
+/// Await `fut`, recording how long it took as `label` if profiling is enabled.
+pub async fn phase<T, F: Future<Output = T>>(label: &'static str, fut: F) -> T {
+    let start = Instant::now();
+    let res = fut.await;
+    let elapsed = start.elapsed();
+    with(|p| {
+        let projects = p.as_mut()?;
+        if projects.is_empty() {
+            projects.push((String::new(), vec![]));
+        }
+        projects.last_mut().map(|(_, phases)| phases.push((label, elapsed)))
+    });
+    res
+}
+
+/// Format the recorded phases, or `None` if profiling is disabled.
+pub fn report() -> Option<String> {
+    with(|p| {
+        let mut out = String::from("Build profile:\n");
+        for (project, phases) in p.as_ref()? {
+            let total: Duration = phases.iter().map(|(_, d)| *d).sum();
+            _ = writeln!(out, "  {project} ({total:.2?})");
+            for (label, d) in phases {
+                _ = writeln!(out, "    {label:<20} {d:.2?}");
+            }
+        }
+        Some(out)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_report() {
+        enable();
+        project("foo");
+        phase("source fetch", async {}).await;
+        phase("rpm build", async {}).await;
+        phase("oci build", async {}).await;
+
+        // other tests may add projects concurrently, so only check that everything is listed
+        let report = report().unwrap();
+        assert!(report.starts_with("Build profile:\n"));
+        assert!(report.lines().any(|l| l.starts_with("  foo (")), "{report}");
+        for label in ["source fetch", "rpm build", "oci build"] {
+            assert!(report.lines().any(|l| l.starts_with(&format!("    {label} "))), "{report}");
+        }
+    }
+}
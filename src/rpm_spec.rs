@@ -46,6 +46,9 @@ pub struct RPMOptions {
     pub scm_opts: Vec<String>,
     /// Plugin Options (mock)
     pub plugin_opts: Vec<String>,
+    /// Disable network access when building RPMs from the SRPM
+    /// Only used if backend is mock
+    pub offline: bool,
 }
 
 impl RPMOptions {
@@ -64,6 +67,7 @@ impl RPMOptions {
             scm_enable: false,
             scm_opts: Vec::new(),
             plugin_opts: Vec::new(),
+            offline: false,
         }
     }
     pub fn add_extra_repo(&mut self, repo: String) {
@@ -135,6 +139,26 @@ impl RPMBuilder {
     /// # Errors
     /// This inherits errors from `RPMSpecBackend::build()`.
     pub async fn build(&self, spec: &Path, options: &mut RPMOptions) -> Result<Vec<PathBuf>> {
+        self.backend(options).build(spec).await
+    }
+
+    /// Build the RPMs of an existing SRPM.
+    ///
+    /// # Errors
+    /// This inherits errors from `RPMSpecBackend::rebuild()`.
+    pub async fn rebuild(&self, srpm: &Path, options: &mut RPMOptions) -> Result<Vec<PathBuf>> {
+        self.backend(options).rebuild(srpm).await
+    }
+
+    /// Build only the SRPM.
+    ///
+    /// # Errors
+    /// This inherits errors from `RPMSpecBackend::build_srpm()`.
+    pub async fn build_srpm(&self, spec: &Path, options: &mut RPMOptions) -> Result<PathBuf> {
+        self.backend(options).build_srpm(spec).await
+    }
+
+    fn backend(self, options: &mut RPMOptions) -> Box<dyn RPMSpecBackend + Send + Sync> {
         // TODO: take ownership of `options`
         if matches!(self, Self::Mock) {
             let mut mock = MockBackend::new(
@@ -158,8 +182,8 @@ impl RPMBuilder {
             mock.enable_scm(options.scm_enable);
             mock.extend_scm_opts(take(&mut options.scm_opts));
             mock.plugin_opts(take(&mut options.plugin_opts));
-
-            mock.build(spec).await
+            mock.offline(options.offline);
+            Box::new(mock)
         } else {
             let mut rpmbuild =
                 RPMBuildBackend::new(take(&mut options.sources), take(&mut options.resultdir));
@@ -171,8 +195,7 @@ impl RPMBuilder {
             rpmbuild.set_target(take(&mut options.target));
             rpmbuild.with_flags_mut().extend(take(&mut options.with));
             rpmbuild.without_flags_mut().extend(take(&mut options.without));
-
-            rpmbuild.build(spec).await
+            Box::new(rpmbuild)
         }
     }
 }
@@ -185,6 +208,11 @@ pub trait RPMSpecBackend {
     async fn build(&self, spec: &Path) -> Result<Vec<PathBuf>> {
         self.build_rpm(&self.build_srpm(spec).await?).await
     }
+
+    /// Build the RPMs of an existing SRPM
+    async fn rebuild(&self, srpm: &Path) -> Result<Vec<PathBuf>> {
+        self.build_rpm(srpm).await
+    }
 }
 
 pub trait RPMExtraOptions {
@@ -261,6 +289,7 @@ pub struct MockBackend {
     scm_opts: Vec<String>,
     plugin_opts: Vec<String>,
     target: Option<String>,
+    offline: bool,
 }
 
 impl RPMExtraOptions for MockBackend {
@@ -303,6 +332,7 @@ impl MockBackend {
             scm_opts: Vec::new(),
             plugin_opts: Vec::new(),
             target: None,
+            offline: false,
         }
     }
 
@@ -321,6 +351,13 @@ impl MockBackend {
         self.no_mirror = no_mirror;
     }
 
+    /// Disable network access when building RPMs from the SRPM.
+    ///
+    /// The SRPM is still built with network access so that sources can be fetched.
+    pub const fn offline(&mut self, offline: bool) {
+        self.offline = offline;
+    }
+
     pub fn enable_scm(&mut self, enable: bool) {
         self.scm_enable = enable;
     }
@@ -388,6 +425,17 @@ impl MockBackend {
 
         cmd
     }
+
+    /// The mock command building RPMs from `srpm` into `resultdir`
+    pub fn rebuild_command(&self, srpm: &Path, resultdir: &Path) -> Command {
+        let mut cmd = self.mock();
+        cmd.arg("--rebuild").arg(srpm);
+        if !self.offline {
+            cmd.arg("--enable-network");
+        }
+        cmd.arg("--resultdir").arg(resultdir);
+        cmd
+    }
 }
 
 #[async_trait]
@@ -436,9 +484,8 @@ impl RPMSpecBackend for MockBackend {
         Err(eyre!("Failed to find srpm"))
     }
     async fn build_rpm(&self, spec: &Path) -> Result<Vec<PathBuf>> {
-        let mut cmd = self.mock();
         let tmp = tempfile::Builder::new().prefix("anda-rpm").tempdir()?;
-        cmd.arg("--rebuild").arg(spec).arg("--enable-network").arg("--resultdir").arg(tmp.path());
+        let mut cmd = self.rebuild_command(spec, tmp.path());
 
         cmd.log().await?;
 
@@ -521,6 +568,70 @@ impl RPMBuildBackend {
         }
     }
 
+    /// `rpmbuild -bs`, building an SRPM of `spec` from the files in `sourcedir` into `srcrpmdir`.
+    pub fn srpm_command(&self, spec: &Path, sourcedir: &Path, srcrpmdir: &Path) -> Command {
+        let mut cmd = self.rpmbuild();
+        cmd.arg("-bs")
+            .arg(spec)
+            .arg("--define")
+            .arg(format!("_sourcedir {}", sourcedir.display()))
+            .arg("--define")
+            .arg(format!("_srcrpmdir {}", srcrpmdir.display()));
+        cmd
+    }
+
+    /// Build an SRPM of `spec` and its `sources` with `rpmbuild -bs`, without building any RPMs.
+    ///
+    /// The files are copied into a flat `SOURCES` directory first, see [`assemble_sources`].
+    /// Returns the path of the SRPM, which is copied to `resultdir/rpm/srpm`.
+    ///
+    /// # Errors
+    /// - cannot assemble the `SOURCES` directory
+    /// - rpmbuild failed
+    pub async fn make_srpm(&self, spec: &Path, sources: &[PathBuf]) -> Result<PathBuf> {
+        let tmp = TempDir::with_prefix("anda-srpm")?;
+        let sourcedir = assemble_sources(tmp.path(), spec, sources)?;
+        let srcrpmdir = tmp.path().join("SRPMS");
+        self.srpm_command(spec, &sourcedir, &srcrpmdir).log().await?;
+
+        for entry in std::fs::read_dir(&srcrpmdir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().ends_with(".src.rpm") {
+                let srpm_dir = self.resultdir.join("rpm/srpm");
+                std::fs::create_dir_all(&srpm_dir)?;
+                let dest = srpm_dir.join(entry.file_name());
+                std::fs::copy(entry.path(), &dest)?;
+                return Ok(dest);
+            }
+        }
+        Err(eyre!("rpmbuild did not produce an SRPM for {}", spec.display()))
+    }
+
+    /// `rpmbuild --rebuild`, unpacking and building `srpm` in `topdir`.
+    ///
+    /// The RPMs are written to `{topdir}/RPMS`.
+    pub fn rebuild_command(&self, srpm: &Path, topdir: &Path) -> Command {
+        let mut cmd = self.rpmbuild();
+        cmd.arg("--rebuild").arg(srpm).arg("--define").arg(format!("_topdir {}", topdir.display()));
+        cmd
+    }
+
+    /// Copy the RPMs in `dir` to the result directory.
+    fn collect_rpms(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut rpms = Vec::new();
+        for entry in walkdir::WalkDir::new(dir) {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().ends_with(".rpm") {
+                let rpms_dir = self.resultdir.join("rpm/rpms");
+                std::fs::create_dir_all(&rpms_dir)?;
+                let dest = rpms_dir.join(entry.file_name());
+                std::fs::copy(entry.path(), &dest)?;
+                rpms.push(dest);
+            }
+        }
+        Ok(rpms)
+    }
+
     pub fn rpmbuild(&self) -> Command {
         let mut cmd = Command::new("rpmbuild");
 
@@ -543,38 +654,8 @@ impl RPMBuildBackend {
 #[async_trait]
 impl RPMSpecBackend for RPMBuildBackend {
     async fn build_srpm(&self, spec: &Path) -> Result<PathBuf> {
-        let mut cmd = self.rpmbuild();
-        let tmp = tempfile::Builder::new().prefix("anda-srpm").tempdir()?;
-
-        cmd.arg("-br")
-            .arg(spec)
-            .arg("--define")
-            .arg(format!("_sourcedir {}", self.sources.canonicalize()?.display()))
-            .arg("--define")
-            .arg(format!("_srcrpmdir {}", tmp.path().display()));
-
-        cmd.log().await?;
-
-        // find srpm in resultdir using walkdir
-
-        for entry in walkdir::WalkDir::new(tmp.path()) {
-            let entry = entry?;
-            debug!("entry: {:?}", entry.file_name());
-            if entry.file_name().to_string_lossy().ends_with(".src.rpm") {
-                // srpm = Some(entry.path().to_path_buf());
-                // eprintln!("found srpm: {:?}", srpm);
-
-                info!("Moving srpm to resultdir...");
-                // create srpm dir if it doesnt exist
-                let srpm_dir = self.resultdir.join("rpm/srpm");
-                std::fs::create_dir_all(&srpm_dir)?;
-                let dest = srpm_dir.join(entry.file_name());
-                std::fs::copy(entry.path(), &dest)?;
-                return Ok(dest);
-            }
-        }
-
-        todo!()
+        let parsed = crate::sources::parse_spec(spec, &self.macros).await?;
+        self.make_srpm(spec, &source_files(&self.sources, &parsed)).await
     }
 
     async fn build_rpm(&self, spec: &Path) -> Result<Vec<PathBuf>> {
@@ -590,27 +671,15 @@ impl RPMSpecBackend for RPMBuildBackend {
 
         cmd.log().await?;
 
-        let mut rpms = Vec::new();
-
-        // find rpms in resultdir using walkdir
-
-        for entry in walkdir::WalkDir::new(tmp.path()) {
-            let entry = entry?;
-            //eprintln!("entry: {:?}", entry.file_name());
-            if entry.file_name().to_string_lossy().ends_with(".rpm") {
-                //rpms.push(entry.path().to_path_buf());
-                // eprintln!("found rpm: {:?}", rpms);
-
-                let rpms_dir = self.resultdir.join("rpm/rpms");
-                std::fs::create_dir_all(&rpms_dir)?;
-                let dest = rpms_dir.join(entry.file_name());
-                std::fs::copy(entry.path(), dest)?;
-                rpms.push(rpms_dir.join(entry.file_name()));
-            }
-        }
+        self.collect_rpms(tmp.path())
+    }
 
-        //println!("rpms: {:?}", rpms);
-        Ok(rpms)
+    async fn rebuild(&self, srpm: &Path) -> Result<Vec<PathBuf>> {
+        // keep the unpacked SRPM out of ~/rpmbuild
+        std::fs::create_dir_all(&self.resultdir)?;
+        let tmp = tempfile::Builder::new().prefix("anda-rpmbuild").tempdir_in(&self.resultdir)?;
+        self.rebuild_command(srpm, tmp.path()).log().await?;
+        self.collect_rpms(&tmp.path().join("RPMS"))
     }
 
     async fn build(&self, spec: &Path) -> Result<Vec<PathBuf>> {
@@ -656,3 +725,137 @@ impl RPMSpecBackend for RPMBuildBackend {
         Ok(rpms)
     }
 }
+
+/// Create a flat `SOURCES` directory in `dir` with the spec and `sources` copied into it by file
+/// name, like rpmbuild expects.
+///
+/// # Errors
+/// - two files have the same name
+/// - cannot copy the files
+pub fn assemble_sources(dir: &Path, spec: &Path, sources: &[PathBuf]) -> Result<PathBuf> {
+    let sourcedir = dir.join("SOURCES");
+    std::fs::create_dir_all(&sourcedir)?;
+    for file in std::iter::once(spec).chain(sources.iter().map(PathBuf::as_path)) {
+        let name = file.file_name().ok_or_else(|| eyre!("Invalid source: {}", file.display()))?;
+        let dest = sourcedir.join(name);
+        if dest.exists() {
+            return Err(eyre!("Duplicate source file name: {}", name.to_string_lossy()));
+        }
+        std::fs::copy(file, dest)?;
+    }
+    Ok(sourcedir)
+}
+
+/// The `SourceN:` and `PatchN:` files of a spec parsed by [`crate::sources::parse_spec`] that are
+/// present in `dir`.
+///
+/// Other files in `dir` are never included, so that unrelated files in the working tree do not end
+/// up in the SRPM.
+pub fn source_files(dir: &Path, parsed: &str) -> Vec<PathBuf> {
+    crate::sources::spec_sources(parsed)
+        .iter()
+        .map(|source| dir.join(crate::sources::source_file_name(source)))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebuild_command() {
+        let srpm = Path::new("foo-1.0-1.fc40.src.rpm");
+        let mock =
+            MockBackend::new(Some("fedora-40-x86_64".into()), PathBuf::new(), PathBuf::new());
+        let cmd = mock.rebuild_command(srpm, Path::new("out"));
+        let args: Vec<_> = cmd.as_std().get_args().map(|a| a.to_string_lossy()).collect();
+        assert_eq!(
+            args,
+            [
+                "-r",
+                "fedora-40-x86_64",
+                "--rebuild",
+                "foo-1.0-1.fc40.src.rpm",
+                "--enable-network",
+                "--resultdir",
+                "out"
+            ]
+        );
+
+        let rpmbuild = RPMBuildBackend::new(PathBuf::new(), PathBuf::new());
+        let cmd = rpmbuild.rebuild_command(srpm, Path::new("out"));
+        let args: Vec<_> = cmd.as_std().get_args().map(|a| a.to_string_lossy()).collect();
+        assert_eq!(args, ["--rebuild", "foo-1.0-1.fc40.src.rpm", "--define", "_topdir out"]);
+    }
+
+    #[test]
+    fn test_offline_rebuild() {
+        let mut mock = MockBackend::new(None, PathBuf::new(), PathBuf::new());
+        let args = |mock: &MockBackend| -> Vec<String> {
+            let cmd = mock.rebuild_command(Path::new("foo.src.rpm"), Path::new("out"));
+            cmd.as_std().get_args().map(|a| a.to_string_lossy().to_string()).collect()
+        };
+        assert!(args(&mock).iter().any(|a| a == "--enable-network"));
+
+        mock.offline(true);
+        assert_eq!(args(&mock), ["--rebuild", "foo.src.rpm", "--resultdir", "out"]);
+    }
+
+    #[test]
+    fn test_assemble_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = dir.path().join("foo.spec");
+        std::fs::write(&spec, "Name: foo").unwrap();
+        std::fs::create_dir_all(dir.path().join("patches")).unwrap();
+        let patch = dir.path().join("patches/fix.patch");
+        std::fs::write(&patch, "--- a").unwrap();
+        let tarball = dir.path().join("foo-1.0.tar.gz");
+        std::fs::write(&tarball, "").unwrap();
+
+        let out = tempfile::tempdir().unwrap();
+        let sourcedir = assemble_sources(out.path(), &spec, &[patch.clone(), tarball]).unwrap();
+        assert_eq!(sourcedir, out.path().join("SOURCES"));
+        let mut files: Vec<_> = std::fs::read_dir(&sourcedir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["fix.patch", "foo-1.0.tar.gz", "foo.spec"]);
+
+        let out = tempfile::tempdir().unwrap();
+        let err = assemble_sources(out.path(), &spec, &[patch.clone(), patch]).unwrap_err();
+        assert_eq!(err.to_string(), "Duplicate source file name: fix.patch");
+
+        // `build_srpm` only picks up the sources of the spec
+        std::fs::write(dir.path().join("README.md"), "").unwrap();
+        let parsed = "Name: foo\nSource0: https://example.com/foo-1.0.tar.gz\nPatch0: fix.patch\n";
+        let files = source_files(dir.path(), parsed);
+        assert_eq!(files, [dir.path().join("foo-1.0.tar.gz")]);
+    }
+
+    #[test]
+    fn test_srpm_command() {
+        let mut backend = RPMBuildBackend::new(".".into(), "anda-build".into());
+        backend.def_macro("dist", ".fc40");
+        let cmd = backend.srpm_command(
+            Path::new("foo.spec"),
+            Path::new("/tmp/SOURCES"),
+            Path::new("/tmp/SRPMS"),
+        );
+        let args: Vec<_> = cmd.as_std().get_args().map(|a| a.to_string_lossy()).collect();
+        assert_eq!(
+            args,
+            [
+                "-D",
+                "dist .fc40",
+                "-bs",
+                "foo.spec",
+                "--define",
+                "_sourcedir /tmp/SOURCES",
+                "--define",
+                "_srcrpmdir /tmp/SRPMS"
+            ]
+        );
+    }
+}
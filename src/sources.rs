@@ -0,0 +1,480 @@
+//! Cache for remote RPM sources
+//!
+//! Remote `SourceN:` and `PatchN:` URLs are downloaded once into a cache directory, keyed by the
+//! URL and the expected checksum, and copied into a staging directory for each build, together
+//! with the local sources of the spec, so that rpm does not have to fetch them again.
+//!
+//! Sources with a detached signature, e.g. `foo.tar.gz` and `foo.tar.gz.asc`, can be verified
+//! against a keyring with [`verify_sources`].
+
+use crate::util::hash_file;
+use color_eyre::{eyre::eyre, Result};
+use sha2::{Digest, Sha256, Sha512};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, info};
+
+lazy_static::lazy_static! {
+    static ref RE_SOURCE: regex::Regex =
+        regex::Regex::new(r"(?mi)^(?:Source|Patch)\d*\s*:\s*(\S+)\s*$").unwrap();
+    static ref RE_SOURCES_FILE: regex::Regex =
+        regex::Regex::new(r"(?m)^(SHA256|SHA512) \((.+)\) = ([0-9a-fA-F]+)$").unwrap();
+}
+
+/// Expected checksum of a source file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Checksum {
+    Sha256(String),
+    Sha512(String),
+}
+
+impl Checksum {
+    /// Check if the file at `path` matches the checksum.
+    ///
+    /// # Errors
+    /// - cannot read the file
+    pub fn verify(&self, path: &Path) -> Result<bool> {
+        Ok(match self {
+            Self::Sha256(h) => hash_file::<Sha256>(path)?.eq_ignore_ascii_case(h),
+            Self::Sha512(h) => hash_file::<Sha512>(path)?.eq_ignore_ascii_case(h),
+        })
+    }
+}
+
+impl std::fmt::Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sha256(h) => write!(f, "sha256:{h}"),
+            Self::Sha512(h) => write!(f, "sha512:{h}"),
+        }
+    }
+}
+
+/// Parse a Fedora-style `sources` file, e.g. `SHA512 (foo-1.0.tar.gz) = abcd…`.
+///
+/// Returns a map of file names to their checksums.
+pub fn parse_sources_file(content: &str) -> BTreeMap<String, Checksum> {
+    RE_SOURCES_FILE
+        .captures_iter(content)
+        .map(|cap| {
+            let hash = cap[3].to_owned();
+            let sum =
+                if &cap[1] == "SHA256" { Checksum::Sha256(hash) } else { Checksum::Sha512(hash) };
+            (cap[2].to_owned(), sum)
+        })
+        .collect()
+}
+
+/// The file name rpm uses for a source URL, honouring the `#/name` fragment convention.
+pub fn source_file_name(url: &str) -> &str {
+    url.rsplit_once("#/").map_or_else(
+        || url.split(['?', '#']).next().unwrap_or(url).rsplit('/').next().unwrap_or(url),
+        |(_, name)| name,
+    )
+}
+
+pub struct SourceCache {
+    dir: PathBuf,
+    /// Ignore cached files and download everything again
+    refresh: bool,
+}
+
+impl SourceCache {
+    pub const fn new(dir: PathBuf, refresh: bool) -> Self {
+        Self { dir, refresh }
+    }
+
+    /// `$ANDA_SOURCE_CACHE`, or `anda/sources` in the user's cache directory.
+    pub fn default_dir() -> PathBuf {
+        if let Some(dir) = std::env::var_os("ANDA_SOURCE_CACHE") {
+            return dir.into();
+        }
+        std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))
+            .unwrap_or_else(std::env::temp_dir)
+            .join("anda/sources")
+    }
+
+    fn path(&self, url: &str, checksum: Option<&Checksum>) -> PathBuf {
+        let mut key = Sha256::new();
+        key.update(url);
+        if let Some(sum) = checksum {
+            key.update(b"\0");
+            key.update(sum.to_string());
+        }
+        self.dir.join(hex::encode(key.finalize())).join(source_file_name(url))
+    }
+
+    /// Copy the source at `url` to `dest`, downloading it with `download` only if it is not
+    /// cached yet, the cached copy does not match `checksum`, or the cache is being refreshed.
+    ///
+    /// Returns whether the source was downloaded.
+    ///
+    /// # Errors
+    /// - the download failed
+    /// - the downloaded file does not match `checksum`
+    /// - cannot read or write the cache
+    pub async fn fetch<F, Fut>(
+        &self,
+        url: &str,
+        checksum: Option<&Checksum>,
+        dest: &Path,
+        download: F,
+    ) -> Result<bool>
+    where
+        F: FnOnce(String, PathBuf) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let cached = self.path(url, checksum);
+        let valid = |p: &Path| checksum.map_or(Ok(true), |sum| sum.verify(p));
+
+        if !self.refresh && cached.exists() && valid(&cached)? {
+            debug!("Using cached source {url}");
+            std::fs::copy(&cached, dest)?;
+            return Ok(false);
+        }
+
+        info!("Downloading source {url}");
+        let dir = cached.parent().expect("cache path has no parent");
+        std::fs::create_dir_all(dir)?;
+        let tmp = tempfile::NamedTempFile::new_in(dir)?.into_temp_path();
+        download(url.to_owned(), tmp.to_path_buf()).await?;
+        if !valid(&tmp)? {
+            return Err(eyre!("Checksum mismatch for {url}"));
+        }
+        tmp.persist(&cached)?;
+        std::fs::copy(&cached, dest)?;
+        Ok(true)
+    }
+}
+
+/// Remove the source cache at `dir`, returning the size of the removed files in bytes.
+///
+/// # Errors
+/// - cannot read or remove the cache
+pub fn prune_cache(dir: &Path) -> Result<u64> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+    let mut size = 0;
+    for entry in walkdir::WalkDir::new(dir) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            size = entry.metadata()?.len().saturating_add(size);
+        }
+    }
+    std::fs::remove_dir_all(dir)?;
+    Ok(size)
+}
+
+/// Download `url` to `dest` using the HTTP agent from `andax`.
+///
+/// # Errors
+/// - network or IO errors
+pub async fn download(url: String, dest: PathBuf) -> Result<()> {
+    tokio::task::spawn_blocking(move || andax::download(&url, &dest, andax::offline())).await?
+}
+
+/// The spec with its macros expanded by `rpmspec --parse`.
+///
+/// # Errors
+/// - `rpmspec` is not installed or failed to parse the spec, e.g. because of missing macros
+pub async fn parse_spec(spec: &Path, macros: &BTreeMap<String, String>) -> Result<String> {
+    let mut cmd = tokio::process::Command::new("rpmspec");
+    cmd.arg("--parse").arg(spec);
+    for (name, value) in macros {
+        cmd.arg("-D").arg(format!("{name} {value}"));
+    }
+    let out = cmd.output().await.map_err(|e| eyre!("Cannot run rpmspec: {e}"))?;
+    if !out.status.success() {
+        return Err(eyre!("rpmspec failed: {}", String::from_utf8_lossy(&out.stderr)));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// `SourceN:` and `PatchN:` values of a spec parsed by [`parse_spec`], file names or URLs.
+pub fn spec_sources(parsed: &str) -> Vec<String> {
+    RE_SOURCE.captures_iter(parsed).map(|cap| cap[1].to_owned()).collect()
+}
+
+fn is_remote(source: &str) -> bool {
+    ["http://", "https://", "ftp://"].iter().any(|scheme| source.starts_with(scheme))
+}
+
+/// Detached signatures among the source file names, paired with the file they sign.
+pub fn signed_sources(files: &[String]) -> Vec<(&str, &str)> {
+    files
+        .iter()
+        .filter_map(|sig| {
+            let data =
+                [".asc", ".sig", ".sign"].into_iter().find_map(|ext| sig.strip_suffix(ext))?;
+            files.iter().find(|f| *f == data).map(|data| (sig.as_str(), data.as_str()))
+        })
+        .collect()
+}
+
+/// Check the detached signature `sig` of `data` with `gpgv`.
+///
+/// The keyring may be binary or ASCII-armored.
+///
+/// # Errors
+/// - `gpg` or `gpgv` failed to run
+/// - the signature is bad or not made by a key in the keyring
+pub async fn verify_signature(keyring: &Path, sig: &Path, data: &Path) -> Result<()> {
+    let home = tempfile::tempdir()?;
+    let mut keyring = std::path::absolute(keyring)?;
+    if std::fs::read(&keyring)?.starts_with(b"-----BEGIN") {
+        let dearmored = home.path().join("keyring.gpg");
+        let out = tokio::process::Command::new("gpg")
+            .arg("--homedir")
+            .arg(home.path())
+            .args(["--batch", "--dearmor", "--output"])
+            .arg(&dearmored)
+            .arg(&keyring)
+            .output()
+            .await?;
+        if !out.status.success() {
+            return Err(eyre!("Cannot read keyring: {}", String::from_utf8_lossy(&out.stderr)));
+        }
+        keyring = dearmored;
+    }
+    let out = tokio::process::Command::new("gpgv")
+        .arg("--homedir")
+        .arg(home.path())
+        .arg("--keyring")
+        .arg(&keyring)
+        .arg(sig)
+        .arg(data)
+        .output()
+        .await?;
+    if !out.status.success() {
+        let name = data.file_name().unwrap_or_default().to_string_lossy();
+        return Err(eyre!("Bad signature for {name}: {}", String::from_utf8_lossy(&out.stderr)));
+    }
+    Ok(())
+}
+
+/// Verify the sources of a spec parsed by [`parse_spec`] in `sources_dir` that have a detached
+/// signature.
+///
+/// # Errors
+/// - a signature is bad, see [`verify_signature`]
+pub async fn verify_sources(parsed: &str, sources_dir: &Path, keyring: &Path) -> Result<()> {
+    let files: Vec<_> =
+        spec_sources(parsed).iter().map(|s| source_file_name(s).to_owned()).collect();
+    for (sig, data) in signed_sources(&files) {
+        verify_signature(keyring, &sources_dir.join(sig), &sources_dir.join(data)).await?;
+        info!("Verified signature of {data}");
+    }
+    Ok(())
+}
+
+/// Whether `dest` exists and matches `checksum`, if any.
+fn is_present(dest: &Path, checksum: Option<&Checksum>) -> Result<bool> {
+    if !dest.exists() {
+        return Ok(false);
+    }
+    let Some(sum) = checksum else { return Ok(true) };
+    let valid = sum.verify(dest)?;
+    if !valid {
+        info!("{} does not match {sum}, fetching it again", dest.display());
+    }
+    Ok(valid)
+}
+
+/// Put the sources of a spec parsed by [`parse_spec`] into `staging`, replacing its content.
+///
+/// Local sources are copied from `sources_dir`. Remote sources are copied from there as well if
+/// they are present and match their checksum, otherwise they are fetched through the cache.
+/// Checksums are taken from a `sources` file in `sources_dir` if there is one.
+///
+/// # Errors
+/// - a source failed to download or verify
+/// - cannot copy a source into `staging`
+pub async fn stage_sources(
+    cache: &SourceCache,
+    parsed: &str,
+    sources_dir: &Path,
+    staging: &Path,
+) -> Result<()> {
+    let sums = std::fs::read_to_string(sources_dir.join("sources"))
+        .map(|s| parse_sources_file(&s))
+        .unwrap_or_default();
+    if staging.exists() {
+        std::fs::remove_dir_all(staging)?;
+    }
+    std::fs::create_dir_all(staging)?;
+    for source in spec_sources(parsed) {
+        let name = source_file_name(&source);
+        let local = sources_dir.join(name);
+        let dest = staging.join(name);
+        if !is_remote(&source) {
+            // missing local sources are reported by rpm
+            if local.is_file() {
+                std::fs::copy(&local, &dest)?;
+            }
+            continue;
+        }
+        if !cache.refresh && is_present(&local, sums.get(name))? {
+            std::fs::copy(&local, &dest)?;
+            continue;
+        }
+        cache.fetch(&source, sums.get(name), &dest, download).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_cache_reuse() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = SourceCache::new(cache_dir.path().to_path_buf(), false);
+        let url = "https://example.com/foo-1.0.tar.gz";
+        let sum = Checksum::Sha256(hex::encode(Sha256::digest(b"foo")));
+        let downloads = AtomicUsize::new(0);
+        let download = |_: String, dest: PathBuf| {
+            downloads.fetch_add(1, Ordering::Relaxed);
+            std::future::ready(std::fs::write(dest, b"foo").map_err(Into::into))
+        };
+
+        for _ in 0..2 {
+            let build_dir = tempfile::tempdir().unwrap();
+            let dest = build_dir.path().join(source_file_name(url));
+            cache.fetch(url, Some(&sum), &dest, download).await.unwrap();
+            assert_eq!(std::fs::read(dest).unwrap(), b"foo");
+        }
+        assert_eq!(downloads.load(Ordering::Relaxed), 1);
+
+        let cache = SourceCache::new(cache_dir.path().to_path_buf(), true);
+        let dest = cache_dir.path().join("out");
+        assert!(cache.fetch(url, Some(&sum), &dest, download).await.unwrap());
+        assert_eq!(downloads.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_checksum_mismatch() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = SourceCache::new(cache_dir.path().to_path_buf(), false);
+        let sum = Checksum::Sha256(hex::encode(Sha256::digest(b"foo")));
+        let dest = cache_dir.path().join("out");
+        let err = cache
+            .fetch("https://example.com/foo.tar.gz", Some(&sum), &dest, |_, dest| {
+                std::future::ready(std::fs::write(dest, b"bar").map_err(Into::into))
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Checksum mismatch for https://example.com/foo.tar.gz");
+        assert!(!dest.exists());
+    }
+
+    #[test]
+    fn test_is_present() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("foo.tar.gz");
+        let sum = Checksum::Sha256(hex::encode(Sha256::digest(b"foo")));
+        assert!(!is_present(&dest, Some(&sum)).unwrap());
+        std::fs::write(&dest, b"bar").unwrap();
+        assert!(is_present(&dest, None).unwrap());
+        assert!(!is_present(&dest, Some(&sum)).unwrap());
+        std::fs::write(&dest, b"foo").unwrap();
+        assert!(is_present(&dest, Some(&sum)).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_stage_sources() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = SourceCache::new(cache_dir.path().to_path_buf(), false);
+        let dir = tempfile::tempdir().unwrap();
+        let staging = dir.path().join("staging");
+        std::fs::write(dir.path().join("foo.patch"), b"patch").unwrap();
+        std::fs::write(dir.path().join("foo-1.0.tar.gz"), b"foo").unwrap();
+        std::fs::write(dir.path().join("README.md"), b"readme").unwrap();
+        std::fs::create_dir_all(&staging).unwrap();
+        std::fs::write(staging.join("stale.tar.gz"), b"stale").unwrap();
+
+        let parsed = "Name: foo\nSource0: https://example.com/foo-1.0.tar.gz\nPatch0: foo.patch\n";
+        stage_sources(&cache, parsed, dir.path(), &staging).await.unwrap();
+
+        let mut staged: Vec<_> = std::fs::read_dir(&staging)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        staged.sort();
+        assert_eq!(staged, ["foo-1.0.tar.gz", "foo.patch"]);
+        assert_eq!(std::fs::read(staging.join("foo-1.0.tar.gz")).unwrap(), b"foo");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires gpg"]
+    async fn test_verify_signature() {
+        let dir = tempfile::tempdir().unwrap();
+        let gpg = |args: &[&str]| {
+            std::process::Command::new("gpg")
+                .arg("--homedir")
+                .arg(dir.path())
+                .args(["--batch", "--passphrase", ""])
+                .args(args)
+                .current_dir(dir.path())
+                .output()
+        };
+        let gen = gpg(&["--quick-gen-key", "anda <anda@example.com>", "ed25519", "sign", "never"]);
+        assert!(gen.unwrap().status.success());
+        assert!(gpg(&["--armor", "--output", "keyring.asc", "--export"]).unwrap().status.success());
+        std::fs::write(dir.path().join("foo.tar.gz"), b"foo").unwrap();
+        assert!(gpg(&["--detach-sign", "--armor", "foo.tar.gz"]).unwrap().status.success());
+
+        let [keyring, sig, data] =
+            ["keyring.asc", "foo.tar.gz.asc", "foo.tar.gz"].map(|f| dir.path().join(f));
+        verify_signature(&keyring, &sig, &data).await.unwrap();
+
+        std::fs::write(&data, b"bar").unwrap();
+        let err = verify_signature(&keyring, &sig, &data).await.unwrap_err();
+        assert!(err.to_string().starts_with("Bad signature for foo.tar.gz"), "{err}");
+
+        let files = ["foo.tar.gz", "foo.tar.gz.asc", "bar.sig"].map(str::to_owned);
+        assert_eq!(signed_sources(&files), [("foo.tar.gz.asc", "foo.tar.gz")]);
+        _ = std::process::Command::new("gpgconf")
+            .arg("--homedir")
+            .arg(dir.path())
+            .args(["--kill", "gpg-agent"])
+            .status();
+    }
+
+    #[tokio::test]
+    async fn test_prune_cache() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let dir = cache_dir.path().join("sources");
+        let cache = SourceCache::new(dir.clone(), false);
+        let dest = cache_dir.path().join("out");
+        cache
+            .fetch("https://example.com/foo.tar.gz", None, &dest, |_, dest| {
+                std::future::ready(std::fs::write(dest, b"foo").map_err(Into::into))
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(prune_cache(&dir).unwrap(), 3);
+        assert!(!dir.exists());
+        assert!(dest.exists());
+        assert_eq!(prune_cache(&dir).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_sources_file() {
+        let sums = parse_sources_file("SHA512 (foo-1.0.tar.gz) = ABCD\n");
+        assert_eq!(sums.get("foo-1.0.tar.gz"), Some(&Checksum::Sha512("ABCD".to_owned())));
+        assert_eq!(
+            source_file_name("https://example.com/v1.0.tar.gz#/foo-1.0.tar.gz"),
+            "foo-1.0.tar.gz"
+        );
+        assert_eq!(source_file_name("https://example.com/dl/foo.tar.gz?raw=1"), "foo.tar.gz");
+    }
+}
@@ -1,13 +1,62 @@
+use crate::cmd;
 use anda_config::Manifest;
 use andax::{run, RPMSpec};
-use color_eyre::{Result, Section};
+use color_eyre::{eyre::eyre, Result, Section};
+use git2::Repository;
 use itertools::Itertools;
 use std::io::Write;
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
     thread::{self, Builder},
+    time::Duration,
 };
-use tracing::{debug, error, instrument, trace};
+use tracing::{debug, error, info, instrument, trace};
+
+/// What to do with spec files changed by update scripts
+#[derive(Debug, Clone)]
+pub struct CommitOpts {
+    /// Commit message template, see [`commit_message`]
+    pub message: String,
+    /// Push after committing
+    pub push: bool,
+}
+
+/// Replace `%{name}` and `%{version}` in a commit message template.
+#[allow(clippy::literal_string_with_formatting_args)]
+fn commit_message(template: &str, name: &str, version: &str) -> String {
+    template.replace("%{name}", name).replace("%{version}", version)
+}
+
+/// Stage and commit a spec file in the git repository containing it.
+///
+/// The commit is `HEAD` with only the spec replaced, other changes in the index are left staged
+/// but not committed. Returns the new commit and the working directory of the repository.
+///
+/// # Errors
+/// - the spec file is not inside a git repository
+/// - cannot stage the file or create the commit
+fn commit_spec(spec: &Path, msg: &str) -> Result<(git2::Oid, PathBuf)> {
+    let spec = spec.canonicalize()?;
+    let repo = Repository::discover(spec.parent().unwrap_or(&spec))?;
+    let workdir = repo.workdir().ok_or_else(|| eyre!("Repository has no working directory"))?;
+    let rel = spec.strip_prefix(workdir.canonicalize()?)?;
+
+    let mut index = repo.index()?;
+    index.add_path(rel)?;
+    index.write()?;
+    let entry = index.get_path(rel, 0).ok_or_else(|| eyre!("Cannot stage {}", rel.display()))?;
+
+    let parent = repo.head()?.peel_to_commit()?;
+    let mut head_index = git2::Index::new()?;
+    head_index.read_tree(&parent.tree()?)?;
+    head_index.add(&entry)?;
+    let tree = repo.find_tree(head_index.write_tree_to(&repo)?)?;
+    let sig = repo.signature()?;
+    let oid = repo.commit(Some("HEAD"), &sig, &sig, msg, &tree, &[&parent])?;
+    Ok((oid, workdir.to_path_buf()))
+}
 
 /// Return true only if the project `lbls` does not have the key or the value does not match.
 fn filter_project(lbls: &BTreeMap<String, String>) -> impl Fn(&(String, String)) -> bool + '_ {
@@ -26,6 +75,7 @@ pub fn update(
     global_lbls: Vec<(String, String)>,
     fls: Vec<Vec<(String, String)>>,
     excls: Vec<Vec<(String, String)>>,
+    commit: Option<&CommitOpts>,
 ) -> Result<()> {
     let mut handlers = vec![];
     let proj_len = cfg.project.len();
@@ -60,13 +110,18 @@ pub fn update(
                 }
             });
             let duration = start.elapsed().as_millis();
+            let mut updated = None;
             if let Some(sc) = sc {
-                let rpm: RPMSpec = sc.get_value("rpm").expect("No rpm object in rhai scope");
+                let mut rpm: RPMSpec = sc.get_value("rpm").expect("No rpm object in rhai scope");
+                if rpm.changed() {
+                    updated = Some((rpm.spec.clone(), rpm.get_version().unwrap_or_default()));
+                }
                 if let Err(e) = rpm.write() {
                     error!("{name}: Failed to write RPM: {e}");
+                    updated = None;
                 }
             }
-            duration
+            (duration, updated)
         })?);
     }
 
@@ -77,13 +132,15 @@ pub fn update(
     }
     debug!("Joining {hdl_len} threads");
     let mut panicked = Vec::with_capacity(0);
+    let mut updated: Vec<(String, PathBuf, String)> = vec![];
 
     let tasks = handlers
         .into_iter()
         .filter_map(|hdl| {
             let th = hdl.thread();
             let name = th.name().expect("No name for andax thread??").to_owned();
-            if let Ok(duration) = hdl.join() {
+            if let Ok((duration, upd)) = hdl.join() {
+                updated.extend(upd.map(|(spec, ver)| (name.clone(), spec, ver)));
                 Some((name, duration))
             } else {
                 error!("Thread `{name}` panicked. This is most likely a bug.");
@@ -114,6 +171,30 @@ pub fn update(
         writeln!(stdout, "{:<5}{sep}{:>7} {sep} {name}", n + 1, duration).unwrap();
     }
 
+    let mut commit_errs = vec![];
+    if let Some(commit) = commit {
+        let mut repos = BTreeSet::new();
+        for (name, spec, ver) in &updated {
+            let msg = commit_message(&commit.message, name, ver);
+            match commit_spec(spec, &msg) {
+                Ok((oid, workdir)) => {
+                    info!("{name}: Committed {oid}: {msg}");
+                    repos.insert(workdir);
+                }
+                Err(e) => {
+                    error!("{name}: Cannot commit {}: {e:#}", spec.display());
+                    commit_errs.push(format!("Project/alias: {name}: {e:#}"));
+                }
+            }
+        }
+        for workdir in repos.iter().filter(|_| commit.push) {
+            if let Err(e) = cmd!(? "git" "-C" {{workdir.display()}} "push") {
+                error!("Cannot push {}: {e}", workdir.display());
+                commit_errs.push(format!("Repository: {}: {e}", workdir.display()));
+            }
+        }
+    }
+
     if !panicked.is_empty() {
         return Err(panicked.into_iter().fold(
             color_eyre::Report::msg("One of the threads panicked while running the update script")
@@ -122,19 +203,81 @@ pub fn update(
         ));
     }
 
+    if !commit_errs.is_empty() {
+        return Err(commit_errs.into_iter().fold(
+            color_eyre::Report::msg("Cannot commit or push the updated spec files"),
+            Section::warning,
+        ));
+    }
+
     Ok(())
 }
 
+/// Call `run`, then call it again each time one of the files in `paths` changes, until it
+/// returns [`ControlFlow::Break`].
+///
+/// Changes less than `debounce` apart (e.g. an editor saving several times) trigger one run.
+/// The parent directories are watched instead of the files themselves so that editors that save
+/// by replacing the file are handled too.
+///
+/// # Errors
+/// - cannot watch the files
+/// - `run` failed
+pub fn watch<F>(paths: &[String], debounce: Duration, mut run: F) -> Result<()>
+where
+    F: FnMut() -> Result<ControlFlow<()>>,
+{
+    let paths: BTreeSet<PathBuf> = paths.iter().map(std::fs::canonicalize).try_collect()?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut debouncer = notify_debouncer_mini::new_debouncer(debounce, tx)?;
+    for dir in paths.iter().filter_map(|p| p.parent()).unique() {
+        debouncer
+            .watcher()
+            .watch(dir, notify_debouncer_mini::notify::RecursiveMode::NonRecursive)?;
+    }
+
+    while run()?.is_continue() {
+        let changed = loop {
+            let events = rx.recv()?.map_err(|e| eyre!("Cannot watch scripts: {e}"))?;
+            let changed =
+                events.into_iter().map(|e| e.path).filter(|p| paths.contains(p)).collect_vec();
+            if !changed.is_empty() {
+                break changed;
+            }
+        };
+        let changed = changed.iter().map(|p| p.display()).join(", ");
+        println!("\n{} {changed} changed, running again {}\n", "─".repeat(8), "─".repeat(8));
+    }
+    Ok(())
+}
+
+/// Run a script with `labels` and `args` in its scope.
+fn run_script<'a>(
+    name: &'a str,
+    scr: &'a Path,
+    labels: Vec<(String, String)>,
+    args: Vec<String>,
+) -> Option<andax::rhai::Scope<'a>> {
+    run(name, scr, labels.into_iter(), |sc| {
+        sc.push("args", args.into_iter().map(Into::into).collect::<andax::rhai::Array>());
+    })
+}
+
+/// Run each of `scripts` in its own thread, with `labels` and `args` in their scopes.
+///
+/// # Errors
+/// - cannot spawn a thread
 #[instrument]
-pub fn run_scripts(scripts: &[String], labels: Vec<(String, String)>) -> Result<()> {
+pub fn run_scripts(scripts: &[String], labels: &[(String, String)], args: &[String]) -> Result<()> {
     let mut handlers = vec![];
     for scr in scripts {
         trace!(scr, "Th start");
-        let labels = labels.clone();
+        let labels = labels.to_vec();
+        let args = args.to_vec();
         handlers.push(Builder::new().name(scr.to_owned()).spawn(move || {
             let th = thread::current();
             let name = th.name().expect("No name for andax thread??");
-            run(name, &std::path::PathBuf::from(name), labels.into_iter(), |_| {});
+            run_script(name, Path::new(name), labels, args);
         })?);
     }
 
@@ -154,6 +297,41 @@ pub fn run_scripts(scripts: &[String], labels: Vec<(String, String)>) -> Result<
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn test_run_args() {
+        let dir = tempfile::tempdir().unwrap();
+        let scr = dir.path().join("args.rhai");
+        std::fs::write(&scr, "let got = `${args.len()}:${args[0]}:${args[1]}`;").unwrap();
+        let sc = run_script("args", &scr, vec![], vec!["1.0".to_owned(), "foo".to_owned()])
+            .expect("script failed");
+        assert_eq!(sc.get_value::<String>("got").unwrap(), "2:1.0:foo");
+    }
+    #[test]
+    fn test_watch() {
+        let dir = tempfile::tempdir().unwrap();
+        let scr = dir.path().join("watch.rhai");
+        std::fs::write(&scr, "1").unwrap();
+        let paths = [scr.to_string_lossy().to_string()];
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            let mut runs = 0;
+            watch(&paths, Duration::from_millis(100), || {
+                runs += 1;
+                tx.send(runs).unwrap();
+                Ok(if runs == 1 { ControlFlow::Continue(()) } else { ControlFlow::Break(()) })
+            })
+            .unwrap();
+        });
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(10)).unwrap(), 1);
+        // unrelated files in the same directory are ignored
+        std::fs::write(dir.path().join("other"), "").unwrap();
+        std::fs::write(&scr, "2").unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(10)).unwrap(), 2);
+    }
+
     #[test]
     fn test_filter() {
         let transform = |arr: &[(&str, &str)]| {
@@ -171,4 +349,41 @@ mod tests {
             assert!(!test1(&(k, v)));
         }
     }
+
+    #[test]
+    fn test_commit_spec() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let mut cfg = repo.config().unwrap();
+        cfg.set_str("user.name", "anda").unwrap();
+        cfg.set_str("user.email", "anda@example.com").unwrap();
+
+        let spec = dir.path().join("foo.spec");
+        std::fs::write(&spec, "Name: foo\nVersion: 1.0\nRelease: 1%?dist\n").unwrap();
+        let sig = repo.signature().unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "init", &tree, &[]).unwrap();
+
+        let mut rpm = RPMSpec::new("foo".to_owned(), "update.rhai", &spec);
+        rpm.version("v1.1");
+        let ver = rpm.get_version().unwrap();
+        rpm.write().unwrap();
+
+        // staged changes unrelated to the spec stay out of the commit
+        std::fs::write(dir.path().join("other"), "").unwrap();
+        repo.index().unwrap().add_path(Path::new("other")).unwrap();
+        repo.index().unwrap().write().unwrap();
+
+        let msg = commit_message("%{name}: Update to %{version}", "foo", &ver);
+        let (oid, workdir) = commit_spec(&spec, &msg).unwrap();
+        assert_eq!(workdir.canonicalize().unwrap(), dir.path().canonicalize().unwrap());
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.id(), oid);
+        assert_eq!(head.message(), Some("foo: Update to 1.1"));
+        let blob = head.tree().unwrap().get_path(Path::new("foo.spec")).unwrap();
+        let content = repo.find_blob(blob.id()).unwrap().content().to_owned();
+        assert!(String::from_utf8(content).unwrap().contains("Version: 1.1\n"));
+        assert!(head.tree().unwrap().get_path(Path::new("other")).is_err());
+        assert!(repo.index().unwrap().get_path(Path::new("other"), 0).is_some());
+    }
 }
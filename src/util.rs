@@ -28,6 +28,9 @@ pub struct BuildEntry {
     pub pkg: String,
     pub arch: String,
     pub labels: BTreeMap<String, String>,
+    /// Values from the project's `matrix`, passed to `anda build --variant`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variant: BTreeMap<String, String>,
 }
 
 pub fn fetch_build_entries(config: Manifest) -> Vec<BuildEntry> {
@@ -39,32 +42,55 @@ pub fn fetch_build_entries(config: Manifest) -> Vec<BuildEntry> {
     let suffix = config.config.strip_suffix.clone().unwrap_or_default();
 
     let mut entries = Vec::new();
-    for (mut name, project) in config.project {
+    for (name, project) in config.project {
         let dir = name.trim_end_matches(&suffix);
         if !changed_dirs.contains(dir) {
             continue;
         }
-
-        if let Some(rpm) = project.rpm {
-            if rpm.enable_scm.unwrap_or(false) {
-                entries.extend(DEFAULT_ARCHES.iter().map(|arch| BuildEntry {
-                    pkg: std::mem::take(&mut name),
-                    arch: arch.clone(),
-                    labels: project.labels.clone(),
-                }));
-                continue;
-            }
-        }
-        entries.extend(project.arches.unwrap_or_else(|| DEFAULT_ARCHES.to_vec()).into_iter().map(|arch| BuildEntry {
-            pkg: name.clone(),
-            arch,
-            labels: project.labels.clone(),
-        }));
+        entries.extend(project_entries(&name, project));
     }
 
     entries
 }
 
+/// Build entries of a project, one per architecture and [`Project::variants`].
+fn project_entries(name: &str, project: Project) -> Vec<BuildEntry> {
+    let variants = project.variants();
+    let entries: Vec<_> = if project.rpm.as_ref().is_some_and(|rpm| rpm.enable_scm.unwrap_or(false))
+    {
+        DEFAULT_ARCHES
+            .iter()
+            .map(|arch| BuildEntry {
+                pkg: name.to_owned(),
+                arch: arch.clone(),
+                labels: project.labels.clone(),
+                variant: BTreeMap::new(),
+            })
+            .collect()
+    } else {
+        project
+            .arches
+            .unwrap_or_else(|| DEFAULT_ARCHES.to_vec())
+            .into_iter()
+            .map(|arch| BuildEntry {
+                pkg: name.to_owned(),
+                arch,
+                labels: project.labels.clone(),
+                variant: BTreeMap::new(),
+            })
+            .collect()
+    };
+
+    entries
+        .into_iter()
+        .flat_map(|entry| {
+            variants
+                .iter()
+                .map(move |variant| BuildEntry { variant: variant.clone(), ..entry.clone() })
+        })
+        .collect()
+}
+
 /// Command Logging
 ///
 /// This trait implements custom logging for commands in a format of `{command} | {line}`
@@ -247,6 +273,16 @@ pub fn get_date() -> String {
     now.format("%Y%m%d").to_string()
 }
 
+/// Hash a file with `D` (e.g. `sha2::Sha256`) without reading it into memory at once.
+///
+/// # Errors
+/// - cannot read the file
+pub fn hash_file<D: sha2::Digest + Write>(path: &Path) -> std::io::Result<String> {
+    let mut hasher = D::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
 use promptly::prompt_default;
 use tracing::trace;
 
@@ -257,7 +293,11 @@ pub fn init(path: &Path, yes: bool) -> Result<()> {
         std::fs::create_dir_all(path)?;
     }
 
-    let mut config = Manifest { project: BTreeMap::new(), config: anda_config::Config::default() };
+    let mut config = Manifest {
+        project: BTreeMap::new(),
+        config: anda_config::Config::default(),
+        include: Vec::new(),
+    };
 
     // use ignore to scan for files
     let walk = ignore::WalkBuilder::new(path).build();
@@ -411,4 +451,51 @@ mod tests {
 
         fetch_build_entries(config.unwrap());
     }
+    #[test]
+    fn test_matrix_entries() {
+        let project = Project {
+            arches: Some(vec!["x86_64".to_owned()]),
+            matrix: BTreeMap::from([(
+                "python".to_owned(),
+                vec!["3.11".to_owned(), "3.12".to_owned()],
+            )]),
+            ..Default::default()
+        };
+        let entries = project_entries("foo", project);
+        let pythons: Vec<_> = entries.iter().filter_map(|e| e.variant.get("python")).collect();
+        assert_eq!(pythons, ["3.11", "3.12"]);
+        assert!(entries.iter().all(|e| e.pkg == "foo" && e.arch == "x86_64"));
+    }
+    #[test]
+    fn test_scm_entries() {
+        let rpm = anda_config::RpmBuild { enable_scm: Some(true), ..Default::default() };
+        let entries = project_entries("foo", Project { rpm: Some(rpm), ..Default::default() });
+        assert_eq!(entries.len(), DEFAULT_ARCHES.len());
+        assert!(entries.iter().all(|e| e.pkg == "foo"));
+    }
+    #[test]
+    fn test_convert_filter() {
+        use tracing_subscriber::filter::LevelFilter as Filter;
+        assert_eq!(convert_filter(LevelFilter::Off), Filter::OFF);
+        assert_eq!(convert_filter(LevelFilter::Error), Filter::ERROR);
+        assert_eq!(convert_filter(LevelFilter::Warn), Filter::WARN);
+        assert_eq!(convert_filter(LevelFilter::Info), Filter::INFO);
+        assert_eq!(convert_filter(LevelFilter::Debug), Filter::DEBUG);
+        assert_eq!(convert_filter(LevelFilter::Trace), Filter::TRACE);
+    }
+    #[test]
+    fn test_quiet() {
+        use crate::cli::Cli;
+        use clap::Parser;
+        let level = |args: &[&str]| {
+            let cli = Cli::try_parse_from([&["anda"], args, &["list"]].concat()).unwrap();
+            cli.verbose.log_level_filter()
+        };
+        assert_eq!(level(&[]), LevelFilter::Info);
+        assert_eq!(level(&["-vv"]), LevelFilter::Trace);
+        assert_eq!(level(&["-q"]), LevelFilter::Warn);
+        assert_eq!(level(&["-qq"]), LevelFilter::Error);
+        assert_eq!(level(&["-v", "-q"]), LevelFilter::Warn);
+        assert_eq!(level(&["-q", "-vv"]), LevelFilter::Warn);
+    }
 }